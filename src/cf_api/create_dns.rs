@@ -1,17 +1,13 @@
-use super::{CfDnsRecord, DnsOperationResponse};
+use super::{CfClient, CfDnsRecord, DnsOperationResponse};
 use anyhow::Result;
 
 pub fn create_dns_record(
-    client: &reqwest::blocking::Client,
+    client: &CfClient,
     zone_id: &str,
     cf_dns_record: CfDnsRecord,
 ) -> Result<()> {
-    let url = format!(
-        "https://api.cloudflare.com/client/v4
-/zones/{zone_id}/dns_records"
-    );
     let response = client
-        .post(&url)
+        .post(&format!("/zones/{zone_id}/dns_records"))
         .body(serde_json::to_string(&cf_dns_record)?)
         .send()?;
     let text = response.text()?;
@@ -1,57 +1,138 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use super::{CfDnsRecord, Message};
+use super::{
+    paginate::{ListResponse, Paginator, ResultInfo},
+    CfClient, CfDnsRecord,
+};
 
-pub fn list_dns_records(
-    client: &reqwest::blocking::Client,
-    zone_id: &str,
-) -> Result<Vec<DnsRecordResult>> {
-    let url = format!(
-        "https://api.cloudflare.com/client/v4
-/zones/{zone_id}/dns_records"
-    );
-    let response = client.get(&url).send()?;
-    let text = response.text()?;
-    let dns: ListDns = serde_json::from_str(&text)?;
-    if dns.success {
-        return Ok(dns.result);
-    } else {
-        return Err(anyhow::anyhow!("Failed to list dns: {:?}", dns.errors));
+const DNS_RECORDS_PER_PAGE: u32 = 500;
+
+/// 列出 dns 记录时的服务端过滤条件，对应 Cloudflare 的查询参数
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct DnsRecordFilter {
+    /// `type`, 如 A, AAAA
+    pub type_field: Option<String>,
+    /// `name`, 完整域名
+    pub name: Option<String>,
+    /// `name.startswith`
+    pub name_startswith: Option<String>,
+    /// `content`
+    pub content: Option<String>,
+    /// `content.contains`
+    pub content_contains: Option<String>,
+    /// `comment.startswith`
+    pub comment_startswith: Option<String>,
+    /// `comment.contains`
+    pub comment_contains: Option<String>,
+    /// `tag`, `name` 或 `name:value`
+    pub tag: Option<String>,
+}
+
+impl DnsRecordFilter {
+    pub fn type_field(mut self, type_field: &str) -> Self {
+        self.type_field = Some(type_field.to_string());
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn name_startswith(mut self, prefix: &str) -> Self {
+        self.name_startswith = Some(prefix.to_string());
+        self
+    }
+
+    pub fn content(mut self, content: &str) -> Self {
+        self.content = Some(content.to_string());
+        self
+    }
+
+    pub fn content_contains(mut self, part: &str) -> Self {
+        self.content_contains = Some(part.to_string());
+        self
+    }
+
+    pub fn comment_startswith(mut self, prefix: &str) -> Self {
+        self.comment_startswith = Some(prefix.to_string());
+        self
+    }
+
+    pub fn comment_contains(mut self, part: &str) -> Self {
+        self.comment_contains = Some(part.to_string());
+        self
+    }
+
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_string());
+        self
+    }
+
+    pub fn to_query(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("type", &self.type_field),
+            ("name", &self.name),
+            ("name.startswith", &self.name_startswith),
+            ("content", &self.content),
+            ("content.contains", &self.content_contains),
+            ("comment.startswith", &self.comment_startswith),
+            ("comment.contains", &self.comment_contains),
+            ("tag", &self.tag),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_deref().map(|value| (key, value)))
+        .collect()
     }
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ListDns {
-    pub errors: Vec<Message>,
-    // pub messages: Vec<Message>,
-    pub success: bool,
-    // #[serde(rename = "result_info")]
-    // pub result_info: ListDnsInfo,
-    pub result: Vec<DnsRecordResult>,
+pub fn iter_dns_records<'a>(
+    client: &'a CfClient,
+    zone_id: &str,
+    filter: &DnsRecordFilter,
+) -> Paginator<'a, DnsRecordResult> {
+    filter.to_query().into_iter().fold(
+        Paginator::new(
+            client,
+            &format!("/zones/{zone_id}/dns_records"),
+            "dns",
+            DNS_RECORDS_PER_PAGE,
+        ),
+        |paginator, (key, value)| paginator.query(key, value),
+    )
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ListDnsInfo {
-    pub count: Option<i64>,
-    pub page: Option<i64>,
-    #[serde(rename = "per_page")]
-    pub per_page: Option<i64>,
-    #[serde(rename = "total_count")]
-    pub total_count: Option<i64>,
+pub fn list_dns_records(
+    client: &CfClient,
+    zone_id: &str,
+    filter: &DnsRecordFilter,
+) -> Result<Vec<DnsRecordResult>> {
+    iter_dns_records(client, zone_id, filter).collect()
 }
 
+pub type ListDns = ListResponse<DnsRecordResult>;
+
+pub type ListDnsInfo = ResultInfo;
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DnsRecordResult {
     pub comment: Option<String>,
     pub name: String,
-    // pub proxied: bool,
-    // pub tags: Vec<Tag>,
-    // pub ttl: i64,
+    #[serde(default)]
+    pub proxied: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub ttl: i64,
     pub content: String,
+    /// SRV、HTTPS 等记录的结构化内容
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+    /// MX、URI 等记录的优先级
+    #[serde(default)]
+    pub priority: Option<u16>,
     #[serde(rename = "type")]
     pub type_field: String,
     // #[serde(rename = "comment_modified_on")]
@@ -69,14 +150,21 @@ pub struct DnsRecordResult {
     // pub tags_modified_on: Option<String>,
 }
 
-impl Into<CfDnsRecord> for DnsRecordResult {
-    fn into(self) -> CfDnsRecord {
+impl From<DnsRecordResult> for CfDnsRecord {
+    fn from(record: DnsRecordResult) -> Self {
         CfDnsRecord {
-            name: self.name,
-            type_field: self.type_field,
-            content: self.content,
-            comment: self.comment,
-            ..Default::default()
+            name: record.name,
+            type_field: record.type_field,
+            content: record.content,
+            data: record
+                .data
+                .and_then(|data| serde_json::from_value(data).ok()),
+            priority: record.priority,
+            comment: record.comment,
+            proxied: Some(record.proxied),
+            tags: record.tags,
+            ttl: Some(record.ttl),
+            template: None,
         }
     }
 }
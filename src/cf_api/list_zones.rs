@@ -1,42 +1,31 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use super::Message;
-pub fn list_zones(client: &reqwest::blocking::Client) -> Result<Vec<Zone>> {
-    let url = "https://api.cloudflare.com/client/v4
-/zones";
-    let response = client.get(url).send()?;
-    let text = response.text()?;
-    let zones: ListZones = serde_json::from_str(&text)?;
-    if zones.success {
-        return Ok(zones.result);
-    } else {
-        return Err(anyhow::anyhow!("Failed to list zones: {:?}", zones.errors));
-    }
+use super::{
+    paginate::{ListResponse, Paginator, ResultInfo},
+    CfClient,
+};
+
+/// zones 接口单页最多 50 条
+const ZONES_PER_PAGE: u32 = 50;
+
+pub fn iter_zones(client: &CfClient) -> Paginator<'_, Zone> {
+    Paginator::new(client, "/zones", "zones", ZONES_PER_PAGE)
 }
 
-#[derive(Eq, Hash, Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ListZones {
-    pub errors: Vec<Message>,
-    // pub messages: Vec<Message>,
-    pub success: bool,
-    // #[serde(rename = "result_info")]
-    // pub result_info: ListZoneInfo,
-    pub result: Vec<Zone>,
+pub fn list_zones(client: &CfClient) -> Result<Vec<Zone>> {
+    iter_zones(client).collect()
 }
 
-#[derive(Eq, Hash, Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ListZoneInfo {
-    pub count: Option<i64>,
-    pub page: Option<i64>,
-    #[serde(rename = "per_page")]
-    pub per_page: Option<i64>,
-    #[serde(rename = "total_count")]
-    pub total_count: Option<i64>,
+/// 按名称查询单个 zone, 只需要该 zone 的读取权限
+pub fn get_zone_by_name(client: &CfClient, name: &str) -> Result<Option<Zone>> {
+    iter_zones(client).query("name", name).next().transpose()
 }
 
+pub type ListZones = ListResponse<Zone>;
+
+pub type ListZoneInfo = ResultInfo;
+
 #[derive(Eq, Hash, Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Zone {
@@ -1,13 +1,101 @@
-use std::net::IpAddr;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
 
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use crate::config::DnsRecordConfig;
+use crate::config::{Config, DnsRecordConfig, ZoneConfig};
 
 pub mod create_dns;
 pub mod delete_dns;
 pub mod list_dns;
 pub mod list_zones;
+pub mod paginate;
+pub mod update_dns;
+
+pub const DEFAULT_API_URL: &str = "https://api.cloudflare.com/client/v4";
+/// 设置后覆盖配置文件中的 `api_url`，便于指向测试代理或本地 mock
+pub const API_URL_ENV: &str = "CF_DDNS_API_URL";
+
+/// Cloudflare API 客户端，携带鉴权信息和 API 基础地址
+#[derive(Debug, Clone)]
+pub struct CfClient {
+    client: reqwest::blocking::Client,
+    base_url: String,
+    /// zone name -> zone id
+    zone_ids: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl CfClient {
+    pub fn new(token: &str, base_url: &str) -> Result<Self> {
+        let mut auth = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| anyhow::anyhow!("token is not a valid http header value"))?;
+        auth.set_sensitive(true);
+        let client = reqwest::blocking::Client::builder()
+            .default_headers(std::iter::once((reqwest::header::AUTHORIZATION, auth)).collect())
+            .build()?;
+        Ok(Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            zone_ids: Default::default(),
+        })
+    }
+
+    /// 基础地址优先级: 环境变量 > 配置文件 > 默认地址
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let base_url = std::env::var(API_URL_ENV)
+            .ok()
+            .filter(|url| !url.is_empty())
+            .or_else(|| config.api_url.clone())
+            .unwrap_or_else(|| DEFAULT_API_URL.to_string());
+        Self::new(&config.token, &base_url)
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+
+    pub fn get(&self, path: &str) -> reqwest::blocking::RequestBuilder {
+        self.client.get(self.url(path))
+    }
+
+    pub fn post(&self, path: &str) -> reqwest::blocking::RequestBuilder {
+        self.client.post(self.url(path))
+    }
+
+    pub fn patch(&self, path: &str) -> reqwest::blocking::RequestBuilder {
+        self.client.patch(self.url(path))
+    }
+
+    pub fn delete(&self, path: &str) -> reqwest::blocking::RequestBuilder {
+        self.client.delete(self.url(path))
+    }
+
+    /// 获取 zone id, 优先使用配置中的 `zone_id`, 其次是缓存, 最后按名称查询
+    pub fn zone_id(&self, zone: &ZoneConfig) -> Result<String> {
+        if let Some(zone_id) = &zone.zone_id {
+            return Ok(zone_id.clone());
+        }
+        if let Some(zone_id) = self.zone_ids.lock().unwrap().get(&zone.name) {
+            return Ok(zone_id.clone());
+        }
+
+        let found = list_zones::get_zone_by_name(self, &zone.name)?
+            .ok_or_else(|| anyhow::anyhow!("Zone not found: {}", zone.name))?;
+        self.zone_ids
+            .lock()
+            .unwrap()
+            .insert(zone.name.clone(), found.id.clone());
+        Ok(found.id)
+    }
+}
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -15,7 +103,14 @@ pub struct CfDnsRecord {
     pub name: String,
     #[serde(rename = "type")]
     pub type_field: String,
+    /// HTTPS/SVCB/URI 等记录使用 data, content 为空
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<RecordData>,
+    /// URI 记录的优先级，不在 data 中
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
     #[serde(default)]
     pub comment: Option<String>,
     #[serde(default)]
@@ -24,6 +119,9 @@ pub struct CfDnsRecord {
     pub tags: Vec<String>,
     #[serde(default)]
     pub ttl: Option<i64>,
+    /// 由模板生成的内容在填入时间戳之前的样子，用于比较时忽略时间戳
+    #[serde(skip)]
+    pub template: Option<String>,
 }
 
 impl CfDnsRecord {
@@ -36,15 +134,72 @@ impl CfDnsRecord {
             }
             .to_string(),
             content: ip.to_string(),
+            data: None,
+            priority: None,
             comment: dns_config.comment.clone(),
             proxied: Some(dns_config.proxied),
             tags: dns_config.tags.clone(),
             ttl: dns_config.ttl,
-            ..Default::default()
+            template: None,
         }
     }
 }
 
+impl CfDnsRecord {
+    /// 用于显示的记录内容
+    pub fn display_content(&self) -> String {
+        match (&self.data, self.priority) {
+            (Some(data), Some(priority)) => format!("{} {}", priority, data),
+            (Some(data), None) => data.to_string(),
+            (None, _) => self.content.clone(),
+        }
+    }
+}
+
+/// 记录的结构化内容
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RecordData {
+    Svcb(SvcbData),
+    Uri(UriData),
+}
+
+impl std::fmt::Display for RecordData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordData::Svcb(data) => data.fmt(f),
+            RecordData::Uri(data) => data.fmt(f),
+        }
+    }
+}
+
+/// HTTPS/SVCB 记录的 data
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SvcbData {
+    pub priority: u16,
+    pub target: String,
+    pub value: String,
+}
+
+impl std::fmt::Display for SvcbData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.priority, self.target, self.value)
+    }
+}
+
+/// URI 记录的 data，优先级在记录的 `priority` 中
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UriData {
+    pub weight: u16,
+    pub target: String,
+}
+
+impl std::fmt::Display for UriData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} \"{}\"", self.weight, self.target)
+    }
+}
+
 #[derive(Eq, Hash, Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Message {
     pub code: i64,
@@ -56,3 +211,20 @@ pub struct DnsOperationResponse {
     success: bool,
     errors: Vec<Message>,
 }
+
+#[test]
+fn test_client_url() {
+    let client = CfClient::new("token", "http://127.0.0.1:8080/client/v4/").unwrap();
+    assert_eq!(client.base_url(), "http://127.0.0.1:8080/client/v4");
+    assert_eq!(
+        client.url("/zones/abc/dns_records"),
+        "http://127.0.0.1:8080/client/v4/zones/abc/dns_records"
+    );
+    assert_eq!(
+        CfClient::new("token", DEFAULT_API_URL)
+            .unwrap()
+            .url("zones"),
+        "https://api.cloudflare.com/client/v4/zones"
+    );
+    assert!(CfClient::new("tok\nen", DEFAULT_API_URL).is_err());
+}
@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{CfClient, Message};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListResponse<T> {
+    pub errors: Vec<Message>,
+    pub success: bool,
+    #[serde(default)]
+    pub result_info: Option<ResultInfo>,
+    #[serde(default = "Vec::new")]
+    pub result: Vec<T>,
+}
+
+#[derive(Eq, Hash, Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResultInfo {
+    pub count: Option<i64>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    pub total_count: Option<i64>,
+    pub total_pages: Option<i64>,
+}
+
+impl ResultInfo {
+    /// 是否还有下一页，缺少分页信息时视为没有
+    pub fn has_next(&self) -> bool {
+        match (self.page, self.total_pages) {
+            (Some(page), Some(total_pages)) => page < total_pages,
+            _ => false,
+        }
+    }
+}
+
+/// 逐页请求 Cloudflare 列表接口，按条目迭代结果
+pub struct Paginator<'a, T> {
+    client: &'a CfClient,
+    path: String,
+    query: Vec<(String, String)>,
+    what: &'static str,
+    per_page: u32,
+    page: u32,
+    buffer: VecDeque<T>,
+    done: bool,
+}
+
+impl<'a, T: DeserializeOwned> Paginator<'a, T> {
+    pub fn new(client: &'a CfClient, path: &str, what: &'static str, per_page: u32) -> Self {
+        Self {
+            client,
+            path: path.to_string(),
+            query: Vec::new(),
+            what,
+            per_page,
+            page: 0,
+            buffer: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// 附加查询参数，每一页请求都会带上
+    pub fn query(mut self, key: &str, value: &str) -> Self {
+        self.query.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    fn fetch_next_page(&mut self) -> Result<()> {
+        self.page += 1;
+        let response = self
+            .client
+            .get(&self.path)
+            .query(&self.query)
+            .query(&[("page", self.page), ("per_page", self.per_page)])
+            .send()?;
+        let text = response.text()?;
+        let list: ListResponse<T> = serde_json::from_str(&text)?;
+        if !list.success {
+            return Err(anyhow::anyhow!(
+                "Failed to list {}: {:?}",
+                self.what,
+                list.errors
+            ));
+        }
+        let has_next = list.result_info.as_ref().is_some_and(ResultInfo::has_next);
+        if list.result.is_empty() || !has_next {
+            self.done = true;
+        }
+        self.buffer.extend(list.result);
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for Paginator<'_, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffer.is_empty() {
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.fetch_next_page() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+#[test]
+fn test_result_info_has_next() {
+    let info: ResultInfo = serde_json::from_str(
+        r#"{"count":20,"page":1,"per_page":20,"total_count":45,"total_pages":3}"#,
+    )
+    .unwrap();
+    assert!(info.has_next());
+
+    let last = ResultInfo {
+        page: Some(3),
+        ..info.clone()
+    };
+    assert!(!last.has_next());
+    assert!(!ResultInfo::default().has_next());
+}
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{CfClient, DnsOperationResponse, RecordData};
+
+/// PATCH 请求体, 只会修改设置了的字段, 记录 id 和其他元数据保持不变
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DnsRecordPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<RecordData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<i64>,
+}
+
+impl DnsRecordPatch {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+pub fn update_dns_record(
+    client: &CfClient,
+    zone_id: &str,
+    dns_record_id: &str,
+    patch: &DnsRecordPatch,
+) -> Result<()> {
+    let response = client
+        .patch(&format!("/zones/{zone_id}/dns_records/{dns_record_id}"))
+        .body(serde_json::to_string(patch)?)
+        .send()?;
+    let text = response.text()?;
+    let resp: DnsOperationResponse = serde_json::from_str(&text)?;
+    if !resp.success {
+        return Err(anyhow::anyhow!("Update Dns Errors: {:#?}", resp.errors));
+    }
+    Ok(())
+}
@@ -1,6 +1,8 @@
 use std::{
+    fmt::Display,
     hash::{DefaultHasher, Hash, Hasher},
-    net::IpAddr,
+    net::{IpAddr, Ipv6Addr},
+    path::{Path, PathBuf},
 };
 
 use serde::{Deserialize, Serialize};
@@ -11,6 +13,24 @@ pub struct Config {
     pub token: String,
     #[serde(default = "default_interval")]
     pub interval: u64,
+    /// 监听网卡地址变化，变化后立即同步，只在 Linux 上可用，仍然会每隔 interval 同步一次
+    #[serde(default)]
+    pub watch: bool,
+    /// Cloudflare API 基础地址，默认 https://api.cloudflare.com/client/v4
+    #[serde(default)]
+    pub api_url: Option<String>,
+    /// 公网ip的获取方式
+    #[serde(default)]
+    pub ip: IpConfig,
+    /// 标记本设备记录的方式
+    #[serde(default)]
+    pub ownership: OwnershipStrategy,
+    /// 迁移前使用的标记方式，带有这些标记的记录同样属于本设备，同步时会改为当前的标记
+    #[serde(default)]
+    pub ownership_migrate_from: Vec<OwnershipStrategy>,
+    /// 配置中去掉的记录名下的记录如何处理
+    #[serde(default)]
+    pub prune: PrunePolicy,
     #[serde(default)]
     pub zones: Vec<ZoneConfig>,
 }
@@ -19,10 +39,343 @@ fn default_interval() -> u64 {
     60
 }
 
+/// 每个协议族的 ip 来源，列表为空表示不获取该协议族
+#[derive(Hash, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IpConfig {
+    #[serde(default)]
+    pub mode: IpLookupMode,
+    /// quorum 模式下至少需要多少个来源返回同一个地址，默认为过半数
+    #[serde(default)]
+    pub quorum: Option<usize>,
+    /// 连续多少次确认某个协议族没有地址后才删除对应的记录
+    #[serde(default = "default_absent_after")]
+    pub absent_after: u32,
+    #[serde(default = "default_v4_providers")]
+    pub v4: Vec<IpProviderConfig>,
+    #[serde(default = "default_v6_providers")]
+    pub v6: Vec<IpProviderConfig>,
+}
+
+impl Default for IpConfig {
+    fn default() -> Self {
+        Self {
+            mode: IpLookupMode::default(),
+            quorum: None,
+            absent_after: default_absent_after(),
+            v4: default_v4_providers(),
+            v6: default_v6_providers(),
+        }
+    }
+}
+
+impl IpConfig {
+    /// 指定数量的来源需要的票数
+    pub fn quorum_of(&self, providers: usize) -> usize {
+        self.quorum.unwrap_or(providers / 2 + 1)
+    }
+}
+
+#[derive(Default, Hash, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrunePolicy {
+    /// 删除
+    #[default]
+    Delete,
+    /// 保留，不做任何处理
+    Keep,
+    /// 保留，每次同步时输出警告
+    Warn,
+}
+
+#[derive(Default, Hash, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OwnershipStrategy {
+    /// comment 中的 `[device]`
+    #[default]
+    Comment,
+    /// `cf-ddns:device=<device>` 标签
+    Tag,
+    /// 同名的 `_cf-ddns.` TXT 记录，内容为 `heritage=cf-ddns,device=<device>,type=<type>`
+    Txt,
+}
+
+#[derive(Default, Hash, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpLookupMode {
+    /// 按顺序尝试，第一个成功的结果生效
+    #[default]
+    First,
+    /// 同时查询所有来源，只接受足够多来源一致的地址
+    Quorum,
+}
+
+fn default_absent_after() -> u32 {
+    3
+}
+
+fn default_v4_providers() -> Vec<IpProviderConfig> {
+    vec![IpProviderConfig::Http {
+        url: "https://ipv4.icanhazip.com/".to_string(),
+    }]
+}
+
+fn default_v6_providers() -> Vec<IpProviderConfig> {
+    vec![IpProviderConfig::Http {
+        url: "https://ipv6.icanhazip.com/".to_string(),
+    }]
+}
+
+#[derive(Hash, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IpProviderConfig {
+    /// 返回纯文本ip的 http 接口
+    Http { url: String },
+    /// 返回 json 的 http 接口，`field` 为 `.` 分隔的字段路径，如 `data.ip`
+    Json { url: String, field: String },
+    /// Cloudflare 的 /cdn-cgi/trace
+    CfTrace {
+        #[serde(default)]
+        url: Option<String>,
+    },
+    /// 本机网卡上的地址，`interfaces` 为空时使用所有网卡
+    Interface {
+        #[serde(default)]
+        interfaces: Vec<String>,
+        /// 发布所有符合条件的地址，默认只发布第一个
+        #[serde(default)]
+        multiple: bool,
+        /// 包含私有、ULA、链路本地等地址，用于内网解析
+        #[serde(default)]
+        include_private: bool,
+        /// 包含 IPv6 临时地址（隐私扩展），只在 Linux 上能区分
+        #[serde(default)]
+        include_temporary: bool,
+    },
+    /// 执行命令，从标准输出中读取ip，如 `["curl", "-s", "https://ifconfig.me"]`
+    Command { command: Vec<String> },
+    /// 固定的地址
+    Static { addresses: Vec<IpAddr> },
+}
+
+/// 加载配置文件时的错误
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        /// 从 1 开始
+        line: usize,
+        /// 从 1 开始
+        column: usize,
+        /// 出错的字段，如 `zones.records.ttl`
+        field: Option<String>,
+        message: String,
+    },
+    /// 格式正确但内容有误
+    Invalid {
+        path: PathBuf,
+        problems: Vec<ConfigProblem>,
+    },
+}
+
+/// 配置中的一处语义错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// 出错的字段，如 `zones[0].records[1].ttl`
+    pub field: String,
+    pub message: String,
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read { path, source } => {
+                write!(f, "Failed to read {}: {}", path.display(), source)
+            }
+            ConfigError::Parse {
+                path,
+                line,
+                column,
+                field,
+                message,
+            } => {
+                write!(f, "{}:{}:{}: ", path.display(), line, column)?;
+                if let Some(field) = field {
+                    write!(f, "field `{}`: ", field)?;
+                }
+                write!(f, "{}", message)
+            }
+            ConfigError::Invalid { path, problems } => {
+                write!(f, "{}: {} problem(s) found", path.display(), problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Read { source, .. } => Some(source),
+            ConfigError::Parse { .. } | ConfigError::Invalid { .. } => None,
+        }
+    }
+}
+
+impl ConfigError {
+    fn parse(path: &Path, text: &str, error: toml::de::Error) -> Self {
+        let message = error.message().trim().to_string();
+        let offset = error.span().map(|span| span.start).unwrap_or_default();
+        let before = &text[..offset.min(text.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count()
+            + 1;
+
+        // 出错位置所在的表，如 [[zones.records]]
+        let table = before
+            .lines()
+            .rev()
+            .map(str::trim)
+            .find(|l| l.starts_with('['))
+            .map(|l| l.trim_matches(|c| c == '[' || c == ']').trim().to_string());
+        // 缺少字段时从错误信息中取字段名，否则取出错行的键名
+        let key = message
+            .strip_prefix("missing field `")
+            .and_then(|rest| rest.split('`').next())
+            .map(str::to_string)
+            .or_else(|| {
+                let current = text[before.rfind('\n').map_or(0, |i| i + 1)..]
+                    .lines()
+                    .next()
+                    .unwrap_or_default();
+                current
+                    .split_once('=')
+                    .map(|(key, _)| key.trim().trim_matches('"').to_string())
+                    .filter(|key| !key.is_empty() && !key.starts_with('['))
+            });
+        let field = match (table, key) {
+            (Some(table), Some(key)) => Some(format!("{}.{}", table, key)),
+            (None, key) => key,
+            (table, None) => table,
+        };
+
+        ConfigError::Parse {
+            path: path.to_path_buf(),
+            line,
+            column,
+            field,
+            message,
+        }
+    }
+}
+
 impl Config {
-    pub fn load() -> Self {
-        let config = std::fs::read_to_string("config.toml").expect("Failed to read ./config.toml");
-        toml::from_str(&config).unwrap()
+    /// 读取、解析并校验配置文件
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let config = Self::parse(path, &text)?;
+        config.validate().map_err(|problems| ConfigError::Invalid {
+            path: path.to_path_buf(),
+            problems,
+        })?;
+        Ok(config)
+    }
+
+    /// 检查配置的语义，一次返回所有问题
+    pub fn validate(&self) -> Result<(), Vec<ConfigProblem>> {
+        let mut problems = Vec::new();
+        let mut problem =
+            |field: String, message: String| problems.push(ConfigProblem { field, message });
+
+        if self.device.trim().is_empty() {
+            problem("device".to_string(), "must not be empty".to_string());
+        } else if self.device.contains(['[', ']']) {
+            problem(
+                "device".to_string(),
+                "must not contain `[` or `]`".to_string(),
+            );
+        }
+        if self.token.trim().is_empty() {
+            problem("token".to_string(), "must not be empty".to_string());
+        } else if !self.token.chars().all(|c| c.is_ascii_graphic()) {
+            problem(
+                "token".to_string(),
+                "must only contain printable ascii characters without spaces".to_string(),
+            );
+        }
+        if self.interval == 0 {
+            problem("interval".to_string(), "must be greater than 0".to_string());
+        }
+
+        if self.ip.absent_after == 0 {
+            problem(
+                "ip.absent_after".to_string(),
+                "must be greater than 0".to_string(),
+            );
+        }
+        for (family, providers) in [("v4", &self.ip.v4), ("v6", &self.ip.v6)] {
+            if self.ip.mode == IpLookupMode::Quorum && !providers.is_empty() {
+                let quorum = self.ip.quorum_of(providers.len());
+                if quorum == 0 || quorum > providers.len() {
+                    problem(
+                        "ip.quorum".to_string(),
+                        format!(
+                            "{} is not allowed, must be between 1 and the {} provider(s) of ip.{}",
+                            quorum,
+                            providers.len(),
+                            family
+                        ),
+                    );
+                }
+            }
+            for (i, provider) in providers.iter().enumerate() {
+                provider.validate(&format!("ip.{}[{}]", family, i), &mut problem);
+            }
+        }
+
+        for (i, zone) in self.zones.iter().enumerate() {
+            let field = format!("zones[{}]", i);
+            if self.zones[..i]
+                .iter()
+                .any(|z| z.name.eq_ignore_ascii_case(&zone.name))
+            {
+                problem(
+                    format!("{}.name", field),
+                    format!("zone `{}` is listed more than once", zone.name),
+                );
+            }
+            zone.validate(&field, &mut problem);
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    pub fn parse(path: &Path, text: &str) -> Result<Self, ConfigError> {
+        toml::from_str(text).map_err(|e| ConfigError::parse(path, text, e))
     }
 
     pub fn hash_code(&self) -> u64 {
@@ -35,9 +388,84 @@ impl Config {
 #[derive(Default, Hash, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ZoneConfig {
     pub name: String,
+    /// 配置后不再按名称查询 zone, 适用于只授权了单个 zone 的 token
+    #[serde(default)]
+    pub zone_id: Option<String>,
     pub records: Vec<DnsRecordConfig>,
 }
 
+impl IpProviderConfig {
+    fn validate(&self, field: &str, problem: &mut impl FnMut(String, String)) {
+        match self {
+            IpProviderConfig::Http { url } | IpProviderConfig::Json { url, .. }
+                if url.trim().is_empty() =>
+            {
+                problem(format!("{}.url", field), "must not be empty".to_string());
+            }
+            IpProviderConfig::Json { field: path, .. } if path.trim().is_empty() => {
+                problem(format!("{}.field", field), "must not be empty".to_string());
+            }
+            IpProviderConfig::Command { command } if command.is_empty() => {
+                problem(
+                    format!("{}.command", field),
+                    "must not be empty".to_string(),
+                );
+            }
+            IpProviderConfig::Static { addresses } if addresses.is_empty() => {
+                problem(
+                    format!("{}.addresses", field),
+                    "must not be empty".to_string(),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ZoneConfig {
+    fn validate(&self, field: &str, problem: &mut impl FnMut(String, String)) {
+        if !is_valid_dns_name(&self.name) {
+            problem(
+                format!("{}.name", field),
+                format!("`{}` is not a valid domain name", self.name),
+            );
+        }
+
+        for (i, record) in self.records.iter().enumerate() {
+            let field = format!("{}.records[{}]", field, i);
+            let fqdn = record.fqdn(&self.name);
+            if self.records[..i]
+                .iter()
+                .any(|r| r.fqdn(&self.name) == fqdn && r.dns_type.overlaps(&record.dns_type))
+            {
+                problem(
+                    format!("{}.name", field),
+                    format!("record `{}` is configured more than once", fqdn),
+                );
+            }
+            record.validate(&self.name, &field, problem);
+        }
+    }
+}
+
+/// 校验域名，允许 `_` 开头的标签和开头的通配符 `*`
+fn is_valid_dns_name(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > 253 {
+        return false;
+    }
+    name.split('.').enumerate().all(|(i, label)| {
+        (i == 0 && label == "*")
+            || (!label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+    })
+}
+
 #[derive(Default, Hash, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DnsType {
     #[serde(rename = "all")]
@@ -47,6 +475,24 @@ pub enum DnsType {
     V4,
     #[serde(rename = "v6")]
     V6,
+    /// TXT 记录，内容为所有地址
+    #[serde(rename = "txt")]
+    Txt,
+    /// TXT 记录，内容为包含所有地址的 SPF，如 `v=spf1 ip4:203.0.113.7 ~all`
+    #[serde(rename = "spf")]
+    Spf,
+    /// HTTPS 记录，地址放在 ipv4hint/ipv6hint 中
+    #[serde(rename = "https")]
+    Https,
+    /// SVCB 记录，地址放在 ipv4hint/ipv6hint 中
+    #[serde(rename = "svcb")]
+    Svcb,
+    /// CNAME 记录，内容由 content 模板生成
+    #[serde(rename = "cname")]
+    Cname,
+    /// URI 记录，目标由 content 模板生成
+    #[serde(rename = "uri")]
+    Uri,
 }
 
 impl DnsType {
@@ -55,6 +501,68 @@ impl DnsType {
             DnsType::All => true,
             DnsType::V4 => ip.is_ipv4(),
             DnsType::V6 => ip.is_ipv6(),
+            _ => true,
+        }
+    }
+
+    /// 生成的 dns 记录类型
+    pub fn record_types(&self) -> &'static [&'static str] {
+        match self {
+            DnsType::All => &["A", "AAAA"],
+            DnsType::V4 => &["A"],
+            DnsType::V6 => &["AAAA"],
+            DnsType::Txt | DnsType::Spf => &["TXT"],
+            DnsType::Https => &["HTTPS"],
+            DnsType::Svcb => &["SVCB"],
+            DnsType::Cname => &["CNAME"],
+            DnsType::Uri => &["URI"],
+        }
+    }
+
+    /// 是否为每个地址生成一条 A/AAAA 记录，否则所有地址合并到一条记录中
+    pub fn is_address(&self) -> bool {
+        matches!(self, DnsType::All | DnsType::V4 | DnsType::V6)
+    }
+
+    /// 两种类型是否会生成同一类记录，CNAME 不能和其他记录同名
+    pub fn overlaps(&self, other: &DnsType) -> bool {
+        *self == DnsType::Cname
+            || *other == DnsType::Cname
+            || self
+                .record_types()
+                .iter()
+                .any(|t| other.record_types().contains(t))
+    }
+}
+
+/// 记录的内容: 固定的地址列表，或者渲染后作为记录内容的模板
+#[derive(Hash, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RecordContent {
+    Addresses(Vec<IpAddr>),
+    /// 支持 `{ipv4}`、`{ipv6}`、`{device}`、`{timestamp}`、`{hostname}`
+    Template(String),
+}
+
+impl Default for RecordContent {
+    fn default() -> Self {
+        RecordContent::Addresses(Vec::new())
+    }
+}
+
+impl RecordContent {
+    /// 固定的地址，使用模板时为空
+    pub fn addresses(&self) -> &[IpAddr] {
+        match self {
+            RecordContent::Addresses(addresses) => addresses,
+            RecordContent::Template(_) => &[],
+        }
+    }
+
+    pub fn template(&self) -> Option<&str> {
+        match self {
+            RecordContent::Addresses(_) => None,
+            RecordContent::Template(template) => Some(template),
         }
     }
 }
@@ -73,6 +581,279 @@ pub struct DnsRecordConfig {
     pub tags: Vec<String>,
     #[serde(default)]
     pub ttl: Option<i64>,
+    /// 这条记录单独使用的 ip 来源，为空时使用 `[ip]` 中的来源
+    #[serde(default)]
+    pub source: Vec<IpProviderConfig>,
+    /// 固定的地址或者内容模板
+    #[serde(default)]
+    pub content: RecordContent,
+    /// IPv6 接口标识，与获取到的 IPv6 地址的前缀拼接，用于发布前缀委派下其他主机的地址
+    #[serde(default)]
+    pub suffix: Option<Ipv6Addr>,
+    /// 拼接 suffix 时使用的前缀长度，默认为 64
+    #[serde(default)]
+    pub prefix_len: Option<u8>,
+    /// HTTPS/SVCB/URI 记录的优先级，默认为 1
+    #[serde(default)]
+    pub priority: Option<u16>,
+    /// URI 记录的权重，默认为 1
+    #[serde(default)]
+    pub weight: Option<u16>,
+    /// HTTPS/SVCB 记录的目标，默认为 `.`
+    #[serde(default)]
+    pub target: Option<String>,
+    /// HTTPS/SVCB 记录中 ipv4hint/ipv6hint 之外的参数，如 `alpn="h3,h2"`
+    #[serde(default)]
+    pub params: Option<String>,
+}
+
+impl DnsRecordConfig {
+    fn validate(&self, zone_name: &str, field: &str, problem: &mut impl FnMut(String, String)) {
+        if !self.source.is_empty() && !self.content.addresses().is_empty() {
+            problem(
+                format!("{}.source", field),
+                "can not be used together with content".to_string(),
+            );
+        }
+        for (i, provider) in self.source.iter().enumerate() {
+            provider.validate(&format!("{}.source[{}]", field, i), problem);
+        }
+        if let Some(prefix_len) = self.prefix_len {
+            if self.suffix.is_none() {
+                problem(
+                    format!("{}.prefix_len", field),
+                    "has no effect without suffix".to_string(),
+                );
+            } else if !(1..=127).contains(&prefix_len) {
+                problem(
+                    format!("{}.prefix_len", field),
+                    format!("{} is not allowed, must be between 1 and 127", prefix_len),
+                );
+            }
+        }
+        if self.suffix.is_some() && self.dns_type == DnsType::V4 {
+            problem(
+                format!("{}.suffix", field),
+                "only applies to IPv6, can not be used with type v4".to_string(),
+            );
+        }
+        let addresses = self.content.addresses();
+        if !addresses.is_empty() && !addresses.iter().any(|ip| self.dns_type.related(ip)) {
+            problem(
+                format!("{}.content", field),
+                format!("has no address matching type {:?}", self.dns_type),
+            );
+        }
+        match self.content.template() {
+            Some(template) => {
+                if !matches!(self.dns_type, DnsType::Txt | DnsType::Cname | DnsType::Uri) {
+                    problem(
+                        format!("{}.content", field),
+                        "templates only apply to txt, cname and uri records".to_string(),
+                    );
+                } else if template.trim().is_empty() {
+                    problem(
+                        format!("{}.content", field),
+                        "must not be empty".to_string(),
+                    );
+                }
+                for name in crate::template::variables(template) {
+                    if !crate::template::VARIABLES.contains(&name) {
+                        problem(
+                            format!("{}.content", field),
+                            format!(
+                                "unknown variable `{{{}}}`, available: {}",
+                                name,
+                                crate::template::VARIABLES.join(", ")
+                            ),
+                        );
+                    }
+                }
+            }
+            None if matches!(self.dns_type, DnsType::Cname | DnsType::Uri) => {
+                problem(
+                    format!("{}.content", field),
+                    format!(
+                        "{} records need a content template",
+                        self.dns_type.record_types()[0]
+                    ),
+                );
+            }
+            None => {}
+        }
+
+        if self.proxied && !self.dns_type.is_address() && self.dns_type != DnsType::Cname {
+            problem(
+                format!("{}.proxied", field),
+                format!("{:?} records can not be proxied", self.dns_type),
+            );
+        }
+        let svcb = matches!(self.dns_type, DnsType::Https | DnsType::Svcb);
+        for (key, set, applies, types) in [
+            (
+                "priority",
+                self.priority.is_some(),
+                svcb || self.dns_type == DnsType::Uri,
+                "https, svcb and uri",
+            ),
+            ("target", self.target.is_some(), svcb, "https and svcb"),
+            ("params", self.params.is_some(), svcb, "https and svcb"),
+            (
+                "weight",
+                self.weight.is_some(),
+                self.dns_type == DnsType::Uri,
+                "uri",
+            ),
+        ] {
+            if set && !applies {
+                problem(
+                    format!("{}.{}", field, key),
+                    format!("only applies to {} records", types),
+                );
+            }
+        }
+        if svcb && self.priority == Some(0) {
+            problem(
+                format!("{}.priority", field),
+                "0 (alias mode) can not carry address hints, must be greater than 0".to_string(),
+            );
+        }
+
+        if self.name.trim().is_empty() {
+            problem(format!("{}.name", field), "must not be empty".to_string());
+        } else if !is_valid_dns_name(&self.fqdn(zone_name)) {
+            problem(
+                format!("{}.name", field),
+                format!("`{}` is not a valid dns name", self.name),
+            );
+        }
+
+        if let Some(ttl) = self.ttl {
+            // 1 表示自动
+            if ttl != 1 && !(60..=86400).contains(&ttl) {
+                problem(
+                    format!("{}.ttl", field),
+                    format!(
+                        "{} is not allowed, must be 1 (auto) or between 60 and 86400",
+                        ttl
+                    ),
+                );
+            } else if self.proxied && ttl != 1 {
+                problem(
+                    format!("{}.ttl", field),
+                    format!(
+                        "{} has no effect on proxied records, remove it or set it to 1",
+                        ttl
+                    ),
+                );
+            }
+        }
+    }
+
+    /// 记录在 zone 中的完整域名, `@` 表示 zone 本身。
+    /// Cloudflare 返回的记录名都是小写，这里同样转为小写以便比较
+    pub fn fqdn(&self, zone_name: &str) -> String {
+        let zone_name = zone_name.trim_end_matches('.').to_ascii_lowercase();
+        let name = self.name.trim_end_matches('.').to_ascii_lowercase();
+        if name == "@" || name.is_empty() || name == zone_name {
+            zone_name
+        } else if name.ends_with(&format!(".{zone_name}")) {
+            name
+        } else {
+            format!("{name}.{zone_name}")
+        }
+    }
+}
+
+#[test]
+fn test_record_fqdn() {
+    let record = |name: &str| DnsRecordConfig {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    assert_eq!(record("@").fqdn("example.com"), "example.com");
+    assert_eq!(record("www").fqdn("example.com"), "www.example.com");
+    assert_eq!(
+        record("www.example.com").fqdn("example.com"),
+        "www.example.com"
+    );
+    assert_eq!(record("example.com").fqdn("example.com"), "example.com");
+    assert_eq!(record("WWW").fqdn("Example.COM"), "www.example.com");
+    assert_eq!(record("@").fqdn("Example.COM."), "example.com");
+}
+
+#[test]
+fn test_config_parse_error() {
+    let text = r#"
+device = "home"
+token = "token"
+
+[[zones]]
+name = "example.com"
+
+[[zones.records]]
+name = "www"
+ttl = "sixty"
+"#;
+    match Config::parse(Path::new("config.toml"), text) {
+        Err(ConfigError::Parse {
+            line, field, path, ..
+        }) => {
+            assert_eq!(path, Path::new("config.toml"));
+            assert_eq!(line, 10);
+            assert_eq!(field.as_deref(), Some("zones.records.ttl"));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    match Config::parse(Path::new("config.toml"), "token = \"token\"\n") {
+        Err(ConfigError::Parse { field, .. }) => assert_eq!(field.as_deref(), Some("device")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_config_validate() {
+    let text = r#"
+device = ""
+token = "token"
+
+[[zones]]
+name = "example.com"
+
+[[zones.records]]
+name = "www"
+type = "v4"
+ttl = 30
+
+[[zones.records]]
+name = "www.example.com"
+
+[[zones.records]]
+name = "bad_-name-"
+proxied = true
+ttl = 120
+
+[[zones]]
+name = "example.com"
+records = []
+"#;
+    let problems = Config::parse(Path::new("config.toml"), text)
+        .unwrap()
+        .validate()
+        .unwrap_err();
+    let fields: Vec<_> = problems.iter().map(|p| p.field.as_str()).collect();
+    assert_eq!(
+        fields,
+        vec![
+            "device",
+            "zones[0].records[0].ttl",
+            "zones[0].records[1].name",
+            "zones[0].records[2].name",
+            "zones[0].records[2].ttl",
+            "zones[1].name",
+        ]
+    );
 }
 
 #[test]
@@ -85,12 +866,14 @@ fn test_serialization_deserialization() {
         proxied: true,
         tags: vec!["tag1".to_string(), "tag2".to_string()],
         ttl: Some(3600),
+        ..Default::default()
     };
 
     // Create a sample Zone
     let zone = ZoneConfig {
         name: "zone1".to_string(),
         records: vec![record.clone()],
+        ..Default::default()
     };
 
     // Create a sample Config
@@ -0,0 +1,560 @@
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv6Addr},
+    path::{Path, PathBuf},
+};
+
+use crate::config::{Config, DnsRecordConfig, IpConfig, IpLookupMode, IpProviderConfig};
+use provider::IpFamily;
+
+pub mod provider;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_private_ip() {
+        for ip in ["192.168.1.2", "100.64.0.1", "fd00::1", "fe80::1", "::1"] {
+            assert!(is_private_ip(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(!is_private_ip(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_quorum_addrs() {
+        let a: IpAddr = "198.51.100.1".parse().unwrap();
+        let b: IpAddr = "198.51.100.2".parse().unwrap();
+        let results = [vec![a], vec![a, a], vec![b]];
+        assert_eq!(quorum_addrs(results.iter(), 2), vec![a]);
+        assert!(quorum_addrs(results.iter(), 3).is_empty());
+    }
+
+    #[test]
+    fn test_with_suffix() {
+        let ip = "2001:db8:1234:5600:aaaa:bbbb:cccc:dddd".parse().unwrap();
+        let suffix = "::1234:5678".parse().unwrap();
+        assert_eq!(
+            with_suffix(ip, suffix, 64),
+            "2001:db8:1234:5600::1234:5678".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            with_suffix(ip, "0:0:0:12::1".parse().unwrap(), 56),
+            "2001:db8:1234:5612::1".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_absent_after() {
+        let previous = IpCache(vec!["198.51.100.1".parse().unwrap()]);
+        let config = IpConfig {
+            v4: vec![IpProviderConfig::Static { addresses: vec![] }],
+            v6: vec![],
+            ..Default::default()
+        };
+        let mut absent = AbsentCount::default();
+        // 前两次确认没有地址时仍然沿用旧地址，第三次才删除
+        for _ in 0..2 {
+            assert_eq!(
+                IpCache::new(&config, &previous, &mut absent),
+                (previous.clone(), vec![])
+            );
+        }
+        assert_eq!(
+            IpCache::new(&config, &previous, &mut absent),
+            (IpCache::default(), vec![])
+        );
+        assert_eq!(absent.public[&IpFamily::V4], 3);
+    }
+
+    #[test]
+    fn test_source_absent_after() {
+        let record = DnsRecordConfig {
+            name: "lan".to_string(),
+            source: vec![IpProviderConfig::Static { addresses: vec![] }],
+            ..Default::default()
+        };
+        let config = Config {
+            zones: vec![crate::config::ZoneConfig {
+                name: "example.com".to_string(),
+                records: vec![record.clone(), record.clone()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut absent = AbsentCount::default();
+        // 确认没有地址的前两次不修改记录，第三次才删除
+        for _ in 0..2 {
+            let ips = Ips::lookup(&config, vec![], vec![], &mut absent);
+            assert!(ips.for_record(&record, IpFamily::V4).is_none());
+        }
+        let ips = Ips::lookup(&config, vec![], vec![], &mut absent);
+        assert_eq!(ips.for_record(&record, IpFamily::V4), Some(vec![]));
+        assert_eq!(absent.sources.len(), 2);
+
+        // 不再使用的来源被清除
+        Ips::lookup(&Config::default(), vec![], vec![], &mut absent);
+        assert!(absent.sources.is_empty());
+    }
+
+    #[test]
+    fn test_unresolved_without_cache() {
+        let config = IpConfig {
+            v4: vec![IpProviderConfig::Static {
+                addresses: vec!["198.51.100.1".parse().unwrap()],
+            }],
+            v6: vec![IpProviderConfig::Static { addresses: vec![] }],
+            ..Default::default()
+        };
+        // 没有旧地址时，还没确认没有地址的协议族是无法确定的，而不是没有地址
+        let mut absent = AbsentCount::default();
+        let (cache, unresolved) = IpCache::new(&config, &IpCache::default(), &mut absent);
+        assert_eq!(cache.0, vec!["198.51.100.1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(unresolved, vec![IpFamily::V6]);
+
+        let ips = Ips {
+            public: cache.0,
+            unresolved,
+            ..Default::default()
+        };
+        let record = DnsRecordConfig::default();
+        assert!(ips.for_record(&record, IpFamily::V4).is_some());
+        assert!(ips.for_record(&record, IpFamily::V6).is_none());
+    }
+
+    #[test]
+    #[ignore = "requests the default providers over the network"]
+    fn test_get_public_ipaddrs() {
+        println!(
+            "{:?}",
+            IpCache::new(
+                &IpConfig::default(),
+                &IpCache::default(),
+                &mut AbsentCount::default()
+            )
+        );
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IpCache(pub Vec<IpAddr>);
+
+const CACHE_FILE: &str = "ip_cache.txt";
+const ABSENT_FILE: &str = "absent.json";
+
+/// 一个协议族的查询结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum FamilyLookup {
+    /// 获取到了地址
+    Found(Vec<IpAddr>),
+    /// 确认当前没有该协议族的地址，例如网卡上没有地址或者网络不可达
+    Absent,
+    /// 查询失败，无法确定是否还有地址
+    Unknown,
+}
+
+/// 每个协议族连续确认没有地址的次数，保存在状态目录中
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AbsentCount {
+    /// `[ip]` 中的来源
+    #[serde(default)]
+    pub public: HashMap<IpFamily, u32>,
+    /// 记录单独配置的来源，键由 source_key 生成
+    #[serde(default)]
+    pub sources: HashMap<String, u32>,
+}
+
+/// 单独配置的来源在 AbsentCount 中的键
+fn source_key(providers: &[IpProviderConfig], family: IpFamily) -> String {
+    format!(
+        "{:?} {}",
+        family,
+        serde_json::to_string(providers).unwrap_or_default()
+    )
+}
+
+impl AbsentCount {
+    pub fn path(state_dir: &Path) -> PathBuf {
+        state_dir.join(ABSENT_FILE)
+    }
+
+    pub fn save(&self, state_dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(state_dir)?;
+        std::fs::write(Self::path(state_dir), serde_json::to_string(self)?)
+    }
+
+    pub fn load(state_dir: &Path) -> Self {
+        std::fs::read_to_string(Self::path(state_dir))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+}
+
+impl IpCache {
+    /// 获取当前的公网ip，查询失败的协议族沿用上一次的地址，
+    /// 连续 absent_after 次确认没有地址后才不再使用该协议族。
+    /// 同时返回无法确定地址、也没有旧地址可以沿用的协议族，这些协议族的记录不应被修改
+    pub fn new(
+        config: &IpConfig,
+        previous: &IpCache,
+        absent: &mut AbsentCount,
+    ) -> (Self, Vec<IpFamily>) {
+        let mut ips = Vec::new();
+        let mut unresolved = Vec::new();
+        for (family, providers) in [(IpFamily::V4, &config.v4), (IpFamily::V6, &config.v6)] {
+            let kept: Vec<_> = previous
+                .0
+                .iter()
+                .filter(|ip| family.matches(ip))
+                .copied()
+                .collect();
+            let count = absent.public.entry(family).or_default();
+            let keep = match lookup_family(config, providers, family) {
+                FamilyLookup::Found(found) => {
+                    *count = 0;
+                    ips.extend(found);
+                    false
+                }
+                FamilyLookup::Unknown => {
+                    eprintln!("Keep the previous {:?} addresses: {:?}", family, kept);
+                    true
+                }
+                FamilyLookup::Absent => {
+                    *count += 1;
+                    if *count < config.absent_after {
+                        eprintln!(
+                            "No {:?} address ({}/{}), keep the previous addresses: {:?}",
+                            family, count, config.absent_after, kept
+                        );
+                    }
+                    *count < config.absent_after
+                }
+            };
+            if keep {
+                // 第一次运行或者缓存丢失时没有旧地址，不能当作没有地址
+                if kept.is_empty() {
+                    unresolved.push(family);
+                }
+                ips.extend(kept);
+            }
+        }
+        (Self(ips), unresolved)
+    }
+
+    pub fn path(state_dir: &Path) -> PathBuf {
+        state_dir.join(CACHE_FILE)
+    }
+
+    pub fn save(&self, state_dir: &Path) -> std::io::Result<()> {
+        let ips = self
+            .0
+            .iter()
+            .map(|ip| ip.to_string())
+            .collect::<Vec<String>>();
+        let ips = ips.join("\n");
+
+        std::fs::create_dir_all(state_dir)?;
+        std::fs::write(Self::path(state_dir), ips)
+    }
+
+    pub fn load(state_dir: &Path) -> Self {
+        let path = Self::path(state_dir);
+        // 不存在则返回默认值
+        if !path.exists() {
+            return Self::default();
+        }
+
+        let ips = std::fs::read_to_string(path).unwrap_or_default();
+        let ips = ips.lines().map(|ip| ip.parse()).collect::<Result<_, _>>();
+        // 文件损坏时当作没有缓存
+        match ips {
+            Ok(ips) => Self(ips),
+            Err(e) => {
+                eprintln!("Failed to parse {}, ignore it: {}", CACHE_FILE, e);
+                Self::default()
+            }
+        }
+    }
+}
+
+/// 一次同步使用的地址
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Ips {
+    /// `[ip]` 中的来源获取到的地址
+    pub public: Vec<IpAddr>,
+    /// 记录单独配置的来源获取到的地址，获取失败的协议族不在其中
+    pub sources: HashMap<(Vec<IpProviderConfig>, IpFamily), Vec<IpAddr>>,
+    /// `[ip]` 中无法确定地址的协议族
+    pub unresolved: Vec<IpFamily>,
+}
+
+impl Ips {
+    /// 获取 `[ip]` 和所有记录单独配置的来源的地址。
+    /// 单独配置的来源查询失败时不修改对应的记录，
+    /// 和 `[ip]` 一样连续 absent_after 次确认没有地址后才删除对应的记录
+    pub fn lookup(
+        config: &Config,
+        public: Vec<IpAddr>,
+        unresolved: Vec<IpFamily>,
+        absent: &mut AbsentCount,
+    ) -> Self {
+        let mut sources = HashMap::new();
+        let mut seen = HashSet::new();
+        for record in config.zones.iter().flat_map(|zone| &zone.records) {
+            for family in [IpFamily::V4, IpFamily::V6] {
+                if record.source.is_empty() || !seen.insert((record.source.clone(), family)) {
+                    continue;
+                }
+                let key = (record.source.clone(), family);
+                let count = absent
+                    .sources
+                    .entry(source_key(&record.source, family))
+                    .or_default();
+                match lookup_family(&config.ip, &record.source, family) {
+                    FamilyLookup::Found(ips) => {
+                        *count = 0;
+                        sources.insert(key, ips);
+                    }
+                    FamilyLookup::Unknown => eprintln!(
+                        "No {:?} address from the source of {}, leave its records untouched",
+                        family, record.name
+                    ),
+                    FamilyLookup::Absent => {
+                        *count += 1;
+                        if *count < config.ip.absent_after {
+                            eprintln!(
+                                "No {:?} address from the source of {} ({}/{}), leave its records untouched",
+                                family, record.name, count, config.ip.absent_after
+                            );
+                        } else {
+                            sources.insert(key, Vec::new());
+                        }
+                    }
+                }
+            }
+        }
+        // 不再使用的来源不需要保存
+        absent.sources.retain(|key, _| {
+            seen.iter()
+                .any(|(providers, family)| source_key(providers, *family) == *key)
+        });
+        Self {
+            public,
+            sources,
+            unresolved,
+        }
+    }
+
+    /// 记录某个协议族使用的地址，无法确定时返回 None
+    pub fn for_record(&self, record: &DnsRecordConfig, family: IpFamily) -> Option<Vec<IpAddr>> {
+        let ips = if !record.content.addresses().is_empty() {
+            record.content.addresses()
+        } else if !record.source.is_empty() {
+            self.sources.get(&(record.source.clone(), family))?
+        } else if self.unresolved.contains(&family) {
+            return None;
+        } else {
+            &self.public
+        };
+        let ips = ips.iter().filter(|ip| family.matches(ip));
+        Some(match (record.suffix, family) {
+            (Some(suffix), IpFamily::V6) => {
+                let prefix_len = record.prefix_len.unwrap_or(DEFAULT_PREFIX_LEN);
+                ips.map(|ip| match ip {
+                    IpAddr::V6(ip) => IpAddr::V6(with_suffix(*ip, suffix, prefix_len)),
+                    ip => *ip,
+                })
+                .collect()
+            }
+            _ => ips.copied().collect(),
+        })
+    }
+}
+
+const DEFAULT_PREFIX_LEN: u8 = 64;
+
+/// 取 ip 的前 prefix_len 位作为前缀，剩余的位使用 suffix
+pub fn with_suffix(ip: Ipv6Addr, suffix: Ipv6Addr, prefix_len: u8) -> Ipv6Addr {
+    let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+    Ipv6Addr::from((u128::from(ip) & mask) | (u128::from(suffix) & !mask))
+}
+
+/// 请求因为本机没有该协议族的网络而失败，说明地址确实不存在而不是临时故障
+fn is_unreachable(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause.downcast_ref::<std::io::Error>().is_some_and(|e| {
+            matches!(
+                e.kind(),
+                std::io::ErrorKind::NetworkUnreachable | std::io::ErrorKind::AddrNotAvailable
+            )
+        })
+    })
+}
+
+/// 一个来源的结果是否确认没有地址
+fn confirms_absent(result: &anyhow::Result<Vec<IpAddr>>) -> bool {
+    match result {
+        Ok(ips) => ips.is_empty(),
+        Err(e) => is_unreachable(e),
+    }
+}
+
+/// 获取一个协议族的地址，没有配置来源的协议族视为没有地址
+pub fn lookup_family(
+    config: &IpConfig,
+    providers: &[IpProviderConfig],
+    family: IpFamily,
+) -> FamilyLookup {
+    if providers.is_empty() {
+        return FamilyLookup::Found(Vec::new());
+    }
+    match config.mode {
+        IpLookupMode::First => lookup_first(providers, family),
+        IpLookupMode::Quorum => lookup_quorum(providers, family, config.quorum_of(providers.len())),
+    }
+}
+
+/// 按顺序尝试配置的来源，返回第一个成功获取到的地址
+fn lookup_first(providers: &[IpProviderConfig], family: IpFamily) -> FamilyLookup {
+    let mut absent = false;
+    for provider in providers.iter().map(IpProviderConfig::build) {
+        let result = provider.lookup(family);
+        absent |= confirms_absent(&result);
+        match result {
+            Ok(ips) if !ips.is_empty() => return FamilyLookup::Found(ips),
+            Ok(_) => eprintln!("No {:?} address from {}", family, provider.name()),
+            Err(e) => eprintln!(
+                "Failed to get {:?} address from {}: {:?}",
+                family,
+                provider.name(),
+                e
+            ),
+        }
+    }
+    if absent {
+        FamilyLookup::Absent
+    } else {
+        FamilyLookup::Unknown
+    }
+}
+
+/// 同时查询所有来源，只接受至少 quorum 个来源返回的地址
+fn lookup_quorum(providers: &[IpProviderConfig], family: IpFamily, quorum: usize) -> FamilyLookup {
+    let results: Vec<(String, anyhow::Result<Vec<IpAddr>>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = providers
+            .iter()
+            .map(|provider| {
+                scope.spawn(move || {
+                    let provider = provider.build();
+                    (provider.name(), provider.lookup(family))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle.join().unwrap_or_else(|_| {
+                    (
+                        "unknown".to_string(),
+                        Err(anyhow::anyhow!("Provider panicked")),
+                    )
+                })
+            })
+            .collect()
+    });
+
+    let accepted = quorum_addrs(
+        results
+            .iter()
+            .filter_map(|(_, result)| result.as_ref().ok()),
+        quorum,
+    );
+    let disagree = results
+        .iter()
+        .any(|(_, result)| !matches!(result, Ok(ips) if *ips == accepted));
+    if disagree {
+        let summary: Vec<_> = results
+            .iter()
+            .map(|(name, result)| match result {
+                Ok(ips) => format!("{}: {:?}", name, ips),
+                Err(e) => format!("{}: {}", name, e),
+            })
+            .collect();
+        eprintln!(
+            "Ip providers disagree on {:?} address (quorum {}): {}",
+            family,
+            quorum,
+            summary.join("; ")
+        );
+    }
+
+    let absent = results
+        .iter()
+        .filter(|(_, result)| confirms_absent(result))
+        .count();
+    if !accepted.is_empty() {
+        FamilyLookup::Found(accepted)
+    } else if absent >= quorum {
+        FamilyLookup::Absent
+    } else {
+        FamilyLookup::Unknown
+    }
+}
+
+/// 统计各来源返回的地址，返回票数达到 quorum 的地址
+fn quorum_addrs<'a>(results: impl Iterator<Item = &'a Vec<IpAddr>>, quorum: usize) -> Vec<IpAddr> {
+    let mut votes: Vec<(IpAddr, usize)> = Vec::new();
+    for ips in results {
+        let mut seen = Vec::new();
+        for ip in ips {
+            if seen.contains(ip) {
+                continue;
+            }
+            seen.push(*ip);
+            match votes.iter_mut().find(|(addr, _)| addr == ip) {
+                Some((_, count)) => *count += 1,
+                None => votes.push((*ip, 1)),
+            }
+        }
+    }
+    votes
+        .into_iter()
+        .filter(|(_, count)| *count >= quorum)
+        .map(|(ip, _)| ip)
+        .collect()
+}
+
+/// 是否为私有、保留或者只在本地有效的地址，这些地址不应该发布到公网 dns
+pub fn is_private_ip(ip: &IpAddr) -> bool {
+    let ip_networks = [
+        IpNetwork::V4("0.0.0.0/8".parse().unwrap()), // 当前网络或本地网络
+        IpNetwork::V4("10.0.0.0/8".parse().unwrap()), // 私有网络A类
+        IpNetwork::V4("100.64.0.0/10".parse().unwrap()), // 运营商级私有网络
+        IpNetwork::V4("127.0.0.0/8".parse().unwrap()), // 本机回环地址
+        IpNetwork::V4("169.254.0.0/16".parse().unwrap()), // 链路本地地址（APIPA）
+        IpNetwork::V4("172.16.0.0/12".parse().unwrap()), // 私有网络B类
+        IpNetwork::V4("192.0.0.0/24".parse().unwrap()), // IANA特殊用途地址
+        IpNetwork::V4("192.0.2.0/24".parse().unwrap()), // 文档和测试使用（TEST-NET-1）
+        IpNetwork::V4("192.168.0.0/16".parse().unwrap()), // 私有网络C类
+        IpNetwork::V4("198.18.0.0/15".parse().unwrap()), // 网络间基准测试地址
+        IpNetwork::V4("198.51.100.0/24".parse().unwrap()), // 文档和测试使用（TEST-NET-2）
+        IpNetwork::V4("203.0.113.0/24".parse().unwrap()), // 文档和测试使用（TEST-NET-3）
+        IpNetwork::V4("224.0.0.0/4".parse().unwrap()), // 组播地址
+        IpNetwork::V4("240.0.0.0/4".parse().unwrap()), // 保留地址
+        IpNetwork::V4("255.255.255.255/32".parse().unwrap()), // 广播地址
+        IpNetwork::V6("::/128".parse().unwrap()),    // IPv6未指定地址
+        IpNetwork::V6("::1/128".parse().unwrap()),   // IPv6本机回环地址
+        IpNetwork::V6("::ffff:0:0/96".parse().unwrap()), // IPv4映射地址
+        IpNetwork::V6("2001:db8::/32".parse().unwrap()), // IPv6文档和测试使用
+        IpNetwork::V6("fc00::/7".parse().unwrap()),  // IPv6私有网络（ULA）
+        IpNetwork::V6("fe80::/10".parse().unwrap()), // IPv6链路本地地址
+        IpNetwork::V6("ff00::/8".parse().unwrap()),  // IPv6组播地址
+    ];
+
+    ip_networks.iter().any(|net| net.contains(*ip))
+}
@@ -0,0 +1,398 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use anyhow::Result;
+use local_ip_address::list_afinet_netifas;
+use serde::{Deserialize, Serialize};
+
+use super::is_private_ip;
+use crate::config::IpProviderConfig;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_TRACE_URL: &str = "https://www.cloudflare.com/cdn-cgi/trace";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn matches(&self, ip: &IpAddr) -> bool {
+        match self {
+            IpFamily::V4 => ip.is_ipv4(),
+            IpFamily::V6 => ip.is_ipv6(),
+        }
+    }
+
+    /// 对应的 dns 记录类型
+    pub fn record_type(&self) -> &'static str {
+        match self {
+            IpFamily::V4 => "A",
+            IpFamily::V6 => "AAAA",
+        }
+    }
+
+    fn unspecified(&self) -> IpAddr {
+        match self {
+            IpFamily::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpFamily::V6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+}
+
+/// ip 来源
+pub trait IpProvider {
+    /// 用于日志
+    fn name(&self) -> String;
+
+    /// 获取指定协议族的地址
+    fn lookup(&self, family: IpFamily) -> Result<Vec<IpAddr>>;
+}
+
+impl IpProviderConfig {
+    pub fn build(&self) -> Box<dyn IpProvider> {
+        match self.clone() {
+            IpProviderConfig::Http { url } => Box::new(HttpTextProvider { url }),
+            IpProviderConfig::Json { url, field } => Box::new(HttpJsonProvider { url, field }),
+            IpProviderConfig::CfTrace { url } => Box::new(CfTraceProvider {
+                url: url.unwrap_or_else(|| DEFAULT_TRACE_URL.to_string()),
+            }),
+            IpProviderConfig::Interface {
+                interfaces,
+                multiple,
+                include_private,
+                include_temporary,
+            } => Box::new(InterfaceProvider {
+                interfaces,
+                multiple,
+                include_private,
+                include_temporary,
+            }),
+            IpProviderConfig::Command { command } => Box::new(CommandProvider { command }),
+            IpProviderConfig::Static { addresses } => Box::new(StaticProvider { addresses }),
+        }
+    }
+}
+
+/// 绑定到指定协议族发起请求，保证拿到的是该协议族的出口地址
+fn http_get(url: &str, family: IpFamily) -> Result<String> {
+    let client = reqwest::blocking::Client::builder()
+        .local_address(family.unspecified())
+        .timeout(HTTP_TIMEOUT)
+        .build()?;
+    let response = client.get(url).send()?.error_for_status()?;
+    Ok(response.text()?)
+}
+
+/// 解析出的地址必须属于请求的协议族
+fn parse_ip(text: &str, family: IpFamily) -> Result<IpAddr> {
+    let ip: IpAddr = text
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Not an ip address: {:?}", text.trim()))?;
+    if !family.matches(&ip) {
+        return Err(anyhow::anyhow!("Got {} while looking up {:?}", ip, family));
+    }
+    Ok(ip)
+}
+
+pub struct HttpTextProvider {
+    pub url: String,
+}
+
+impl IpProvider for HttpTextProvider {
+    fn name(&self) -> String {
+        format!("http({})", self.url)
+    }
+
+    fn lookup(&self, family: IpFamily) -> Result<Vec<IpAddr>> {
+        Ok(vec![parse_ip(&http_get(&self.url, family)?, family)?])
+    }
+}
+
+pub struct HttpJsonProvider {
+    pub url: String,
+    pub field: String,
+}
+
+/// 按 `.` 分隔的路径取 json 中的字符串字段，数字表示数组下标
+fn json_field<'a>(value: &'a serde_json::Value, field: &str) -> Option<&'a str> {
+    let pointer: String = field.split('.').map(|key| format!("/{}", key)).collect();
+    value.pointer(&pointer)?.as_str()
+}
+
+impl IpProvider for HttpJsonProvider {
+    fn name(&self) -> String {
+        format!("json({}, {})", self.url, self.field)
+    }
+
+    fn lookup(&self, family: IpFamily) -> Result<Vec<IpAddr>> {
+        let value: serde_json::Value = serde_json::from_str(&http_get(&self.url, family)?)?;
+        let ip = json_field(&value, &self.field)
+            .ok_or_else(|| anyhow::anyhow!("Field {} not found in {}", self.field, value))?;
+        Ok(vec![parse_ip(ip, family)?])
+    }
+}
+
+pub struct CfTraceProvider {
+    pub url: String,
+}
+
+/// trace 的返回是 `key=value` 的多行文本
+fn trace_ip(text: &str) -> Option<&str> {
+    text.lines().find_map(|line| line.strip_prefix("ip="))
+}
+
+impl IpProvider for CfTraceProvider {
+    fn name(&self) -> String {
+        format!("cf_trace({})", self.url)
+    }
+
+    fn lookup(&self, family: IpFamily) -> Result<Vec<IpAddr>> {
+        let text = http_get(&self.url, family)?;
+        let ip = trace_ip(&text).ok_or_else(|| anyhow::anyhow!("No ip in trace: {:?}", text))?;
+        Ok(vec![parse_ip(ip, family)?])
+    }
+}
+
+pub struct InterfaceProvider {
+    pub interfaces: Vec<String>,
+    pub multiple: bool,
+    pub include_private: bool,
+    pub include_temporary: bool,
+}
+
+// linux/if_addr.h 中的地址标志
+#[cfg(target_os = "linux")]
+const IFA_F_TEMPORARY: u32 = 0x01;
+#[cfg(target_os = "linux")]
+const IFA_F_DADFAILED: u32 = 0x08;
+#[cfg(target_os = "linux")]
+const IFA_F_DEPRECATED: u32 = 0x20;
+#[cfg(target_os = "linux")]
+const IFA_F_TENTATIVE: u32 = 0x40;
+
+/// 解析 /proc/net/if_inet6, 每行为 `地址 网卡序号 前缀长度 scope flags 网卡名`
+#[cfg(target_os = "linux")]
+fn parse_if_inet6(text: &str) -> Vec<(Ipv6Addr, u32)> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let addr = u128::from_str_radix(fields.first()?, 16).ok()?;
+            let flags = u32::from_str_radix(fields.get(4)?, 16).ok()?;
+            Some((Ipv6Addr::from(addr), flags))
+        })
+        .collect()
+}
+
+/// 不适合发布的 IPv6 地址: 临时地址、已弃用、地址冲突或还在检测中的地址
+#[cfg(target_os = "linux")]
+fn unusable_ipv6_addrs(include_temporary: bool) -> Vec<Ipv6Addr> {
+    let mut mask = IFA_F_DADFAILED | IFA_F_DEPRECATED | IFA_F_TENTATIVE;
+    if !include_temporary {
+        mask |= IFA_F_TEMPORARY;
+    }
+    std::fs::read_to_string("/proc/net/if_inet6")
+        .map(|text| parse_if_inet6(&text))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, flags)| flags & mask != 0)
+        .map(|(addr, _)| addr)
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unusable_ipv6_addrs(_include_temporary: bool) -> Vec<Ipv6Addr> {
+    Vec::new()
+}
+
+impl IpProvider for InterfaceProvider {
+    fn name(&self) -> String {
+        format!("interface({})", self.interfaces.join(","))
+    }
+
+    fn lookup(&self, family: IpFamily) -> Result<Vec<IpAddr>> {
+        let unusable = match family {
+            IpFamily::V4 => Vec::new(),
+            IpFamily::V6 => unusable_ipv6_addrs(self.include_temporary),
+        };
+        let mut ips: Vec<IpAddr> = Vec::new();
+        for (name, ip) in list_afinet_netifas()? {
+            if !self.interfaces.is_empty() && !self.interfaces.contains(&name) {
+                continue;
+            }
+            if !family.matches(&ip) || ip.is_loopback() || ip.is_unspecified() {
+                continue;
+            }
+            if !self.include_private && is_private_ip(&ip) {
+                continue;
+            }
+            if matches!(ip, IpAddr::V6(v6) if unusable.contains(&v6)) || ips.contains(&ip) {
+                continue;
+            }
+            ips.push(ip);
+        }
+        if !self.multiple {
+            ips.truncate(1);
+        }
+        Ok(ips)
+    }
+}
+
+pub struct CommandProvider {
+    pub command: Vec<String>,
+}
+
+impl IpProvider for CommandProvider {
+    fn name(&self) -> String {
+        format!("command({})", self.command.join(" "))
+    }
+
+    fn lookup(&self, family: IpFamily) -> Result<Vec<IpAddr>> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("Empty command"))?;
+        let output = run_with_timeout(
+            std::process::Command::new(program).args(args),
+            COMMAND_TIMEOUT,
+        )?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Command exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        // 每个空白分隔的单词都应当是ip
+        String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .map(|ip| {
+                ip.parse()
+                    .map_err(|_| anyhow::anyhow!("Not an ip address: {:?}", ip))
+            })
+            .filter(|ip| ip.as_ref().map_or(true, |ip| family.matches(ip)))
+            .collect()
+    }
+}
+
+/// 运行命令并收集输出，超时后结束命令，避免卡住的脚本阻塞同步
+fn run_with_timeout(
+    command: &mut std::process::Command,
+    timeout: Duration,
+) -> Result<std::process::Output> {
+    use std::{io::Read, process::Stdio, time::Instant};
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // 在单独的线程中读取输出，避免输出过多时写满管道导致命令无法结束
+    let read = |pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = read(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = read(child.stderr.take().map(|p| Box::new(p) as _));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow::anyhow!("Command timed out after {:?}", timeout));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    Ok(std::process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+pub struct StaticProvider {
+    pub addresses: Vec<IpAddr>,
+}
+
+impl IpProvider for StaticProvider {
+    fn name(&self) -> String {
+        "static".to_string()
+    }
+
+    fn lookup(&self, family: IpFamily) -> Result<Vec<IpAddr>> {
+        Ok(self
+            .addresses
+            .iter()
+            .filter(|ip| family.matches(ip))
+            .copied()
+            .collect())
+    }
+}
+
+#[test]
+fn test_parse_provider_responses() {
+    let trace = "fl=123\nh=www.cloudflare.com\nip=203.0.113.7\nts=1700000000.123\n";
+    assert_eq!(trace_ip(trace), Some("203.0.113.7"));
+
+    let value: serde_json::Value =
+        serde_json::from_str(r#"{"data":{"ips":["2001:db8::1"]}}"#).unwrap();
+    assert_eq!(json_field(&value, "data.ips.0"), Some("2001:db8::1"));
+    assert_eq!(json_field(&value, "data.ip"), None);
+
+    assert!(parse_ip("203.0.113.7\n", IpFamily::V4).is_ok());
+    assert!(parse_ip("203.0.113.7", IpFamily::V6).is_err());
+    assert!(parse_ip("<html>login</html>", IpFamily::V4).is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_parse_if_inet6() {
+    let if_inet6 = "20010db8000000000000000000000001 02 40 00 80     eth0\n\
+                    20010db80000000012345678abcdef01 02 40 00 01     eth0\n";
+    assert_eq!(
+        parse_if_inet6(if_inet6),
+        vec![
+            ("2001:db8::1".parse().unwrap(), 0x80),
+            (
+                "2001:db8::1234:5678:abcd:ef01".parse().unwrap(),
+                IFA_F_TEMPORARY
+            ),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command_timeout() {
+    let output = run_with_timeout(
+        std::process::Command::new("sh").args(["-c", "echo 203.0.113.7"]),
+        Duration::from_secs(5),
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "203.0.113.7"
+    );
+
+    let start = std::time::Instant::now();
+    assert!(run_with_timeout(
+        std::process::Command::new("sleep").arg("10"),
+        Duration::from_millis(200),
+    )
+    .is_err());
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
@@ -1,101 +1,123 @@
-use std::collections::HashMap;
+use std::path::Path;
 
 use anyhow::Result;
-use cf_api::{delete_dns::delete_dns_record, list_dns::list_dns_records, list_zones::list_zones};
+use cf_api::{list_dns::DnsRecordResult, CfClient};
 use config::Config;
-use ip::{del_ip_cache, get_ip_difference, has_ip_cache};
+use ip::{AbsentCount, IpCache, Ips};
+use owner::Owner;
+use reconcile::{ManagedZones, Operation};
 
 pub mod cf_api;
 pub mod config;
 pub mod ip;
+pub mod owner;
+pub mod paths;
+pub mod reconcile;
+pub mod template;
+pub mod watch;
 
-pub fn create_client(token: &str) -> reqwest::blocking::Client {
-    reqwest::blocking::Client::builder()
-        .default_headers(
-            std::iter::once((
-                reqwest::header::AUTHORIZATION,
-                format!("Bearer {}", token).parse().unwrap(),
-            ))
-            .collect(),
-        )
-        .build()
-        .unwrap()
-}
-
-pub fn re_init_cfddns(config: Option<Config>) -> Config {
-    // delete ip cache and run with new ip
-    let config = config.unwrap_or_else(|| Config::load());
-    let client = create_client(&config.token);
-    if has_ip_cache() {
-        del_ip_cache();
+/// 根据配置和当前ip同步本设备的记录，只修改和已有记录不一致的部分，
+/// 配置中已经去掉的记录名和 zone 下的记录按 prune 策略处理
+pub fn sync_dns_records(client: &CfClient, config: &Config, state_dir: &Path) -> Result<()> {
+    let Some(ips) = current_ips(config, state_dir) else {
+        anyhow::bail!("failed to get any public ip address");
+    };
+    let mut owner = Owner::new(config);
+    let mut managed = ManagedZones::load(state_dir);
+    let (state, errors) = reconcile::fetch_state(client, config, &mut owner, &managed);
+    for error in &errors {
+        eprintln!("Failed to read {}", error);
     }
-
-    match delete_old_dns_records(&client, &config) {
-        Ok(_) => println!("Deleted old dns records"),
-        Err(e) => eprintln!("Failed to delete old dns records: {:?}", e),
+    let plan = reconcile::plan_from_state(config, &ips, &state);
+    let result = if plan.is_empty() {
+        Ok(())
+    } else {
+        plan.apply(client, &owner)
+    };
+    managed.update(config, &state, result.is_ok());
+    if let Err(e) = managed.save(state_dir) {
+        eprintln!("Failed to save managed zones: {:?}", e);
+    }
+    result?;
+    if !errors.is_empty() {
+        anyhow::bail!("failed to read the records of {} zones", errors.len());
     }
+    Ok(())
+}
 
-    apply_ip_differences(get_ip_difference(), &config);
-    config
+/// 获取当前公网ip，无法确定的协议族沿用缓存中的地址，不修改保存的状态
+pub fn lookup_ips(config: &Config, state_dir: &Path) -> Ips {
+    let mut absent = AbsentCount::load(state_dir);
+    let (public, unresolved) = IpCache::new(&config.ip, &IpCache::load(state_dir), &mut absent);
+    Ips::lookup(config, public.0, unresolved, &mut absent)
 }
 
-pub fn apply_ip_differences(ip_differences: Vec<ip::IpDifference>, config: &Config) {
-    if ip_differences.is_empty() {
-        return;
+/// 获取并缓存当前公网ip，有记录使用公网ip但一个都没有获取到时返回 None
+fn current_ips(config: &Config, state_dir: &Path) -> Option<Ips> {
+    let mut absent = AbsentCount::load(state_dir);
+    let (current, unresolved) = IpCache::new(&config.ip, &IpCache::load(state_dir), &mut absent);
+    let ips = Ips::lookup(config, current.0.clone(), unresolved, &mut absent);
+    if let Err(e) = absent.save(state_dir) {
+        eprintln!("Failed to save absent count: {:?}", e);
     }
-    let client = create_client(&config.token);
-
-    let zones = list_zones(&client).unwrap_or_default();
-    let mut dns_records = HashMap::new();
-    for zone in &zones {
-        let records = list_dns_records(&client, &zone.id).unwrap_or_default();
-        if !records.is_empty() {
-            dns_records.insert(zone.name.to_string(), records);
-        }
+    if let Err(e) = current.save(state_dir) {
+        eprintln!("Failed to save ip cache: {:?}", e);
+    }
+    let uses_public = config
+        .zones
+        .iter()
+        .flat_map(|zone| &zone.records)
+        .any(|record| record.source.is_empty() && record.content.addresses().is_empty());
+    if uses_public && current.0.is_empty() {
+        eprintln!("Failed to get any public ip address, skip syncing");
+        return None;
     }
+    Some(ips)
+}
 
-    for ip_difference in ip_differences {
-        match ip_difference.apply(&client, &config, &zones, &dns_records) {
-            Ok(_) => {
-                println!("Succeed to apply ip_difference [{:?}]", ip_difference);
-            }
-            Err(e) => {
-                eprintln!(
-                    "Failed to apply ip_difference [{:?}]: {:?}",
-                    ip_difference, e
-                );
-            }
+/// 本设备在配置的 zone 中创建的所有记录
+pub fn list_device_records(
+    client: &CfClient,
+    config: &Config,
+) -> Result<Vec<(String, DnsRecordResult)>> {
+    let mut owner = Owner::new(config);
+    let mut records = Vec::new();
+    for zone_config in &config.zones {
+        let zone_id = client.zone_id(zone_config)?;
+        for record in owner.list_records(client, &zone_id)? {
+            records.push((zone_config.name.clone(), record));
         }
     }
+    Ok(records)
 }
 
-pub fn delete_old_dns_records(client: &reqwest::blocking::Client, config: &Config) -> Result<()> {
-    let zones = list_zones(client)?;
-    let public_ips = ip::get_public_ipaddrs();
-    for zone in zones {
-        let dns_records = list_dns_records(client, &zone.id)?;
-        println!("zone: {:?}, dns_records: {:?}", zone, dns_records);
-        for dns_record in dns_records {
-            // delete old dns records by name
-            if let Some(comment) = &dns_record.comment {
-                if comment.starts_with(&format!("[{}]", config.device)) {
-                    match delete_dns_record(client, &zone.id, &dns_record.id) {
-                        Ok(_) => println!("Deleted dns record: {:?}", dns_record),
-                        Err(e) => eprintln!("Failed to delete dns record: {:?}", e),
-                    }
-                }
-            }
-
-            // delete old dns records by ip
-            if public_ips.iter().find(|i| dns_record == **i).is_some() {
-                if !dns_record.name.starts_with(&format!("[{}]", config.device)) {
-                    match delete_dns_record(client, &zone.id, &dns_record.id) {
-                        Ok(_) => println!("Deleted dns record: {:?}", dns_record),
-                        Err(e) => eprintln!("Failed to delete dns record: {:?}", e),
-                    }
-                }
-            }
+/// 删除本设备创建的所有旧记录的操作，包括已经从配置中去掉的 zone，
+/// 手动添加的和其他设备的记录不受影响
+pub fn plan_old_dns_records(
+    client: &CfClient,
+    config: &Config,
+    owner: &mut Owner,
+    managed: &ManagedZones,
+) -> Result<Vec<Operation>> {
+    let mut zones = Vec::new();
+    for zone_config in &config.zones {
+        zones.push((zone_config.name.clone(), client.zone_id(zone_config)?));
+    }
+    zones.extend(
+        managed
+            .dropped(config)
+            .map(|(zone, zone_id)| (zone.to_string(), zone_id.to_string())),
+    );
+    let mut operations = Vec::new();
+    for (zone, zone_id) in zones {
+        for record in owner.list_records(client, &zone_id)? {
+            operations.push(Operation::Delete {
+                zone: zone.clone(),
+                zone_id: zone_id.clone(),
+                record,
+                duplicate_of: None,
+            });
         }
     }
-    Ok(())
+    Ok(operations)
 }
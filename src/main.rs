@@ -1,24 +1,224 @@
-use std::{thread::sleep, time::Duration};
+use std::{path::PathBuf, time::Duration};
 
-use cf_ddns::{apply_ip_differences, config::Config, ip::get_ip_difference, re_init_cfddns};
+use cf_ddns::{
+    cf_api::CfClient,
+    config::Config,
+    list_device_records, lookup_ips,
+    owner::Owner,
+    paths, plan_old_dns_records, reconcile,
+    reconcile::{ManagedZones, Plan},
+    sync_dns_records,
+    watch::{wait_for_change, AddrWatcher},
+};
+use clap::{Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Sync this machine's IP addresses to Cloudflare DNS records"
+)]
+struct Cli {
+    /// Path of the config file
+    #[arg(long, global = true, env = paths::CONFIG_ENV, default_value_os_t = paths::default_config_path())]
+    config: PathBuf,
+    /// Directory to keep runtime state such as the ip cache
+    #[arg(long, global = true, env = paths::STATE_DIR_ENV, default_value_os_t = paths::default_state_dir())]
+    state_dir: PathBuf,
+    /// Only print the operations, never modify any record
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Print plans and records as JSON
+    #[arg(long, global = true)]
+    json: bool,
+    /// Sync immediately when interface addresses change (Linux only), same as `watch = true` in the config
+    #[arg(long, global = true)]
+    watch: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq)]
+enum Command {
+    /// Run as a daemon, syncing every interval (default)
+    Run,
+    /// Sync once and exit, for cron jobs and systemd timers
+    Once,
+    /// Print the operations that would run on startup, then exit
+    Plan,
+    /// Delete the old records created by this device, then exit
+    Cleanup,
+    /// List the records created by this device
+    List,
+    /// Check the config file and report all problems
+    CheckConfig,
+}
+
+fn print_plan(plan: &Plan, json: bool) {
+    if json {
+        match serde_json::to_string_pretty(plan) {
+            Ok(plan) => println!("{}", plan),
+            Err(e) => eprintln!("Failed to serialize plan: {:?}", e),
+        }
+    } else {
+        println!("{}", plan);
+    }
+}
 
 fn main() {
-    // delete ip cache and run with new ip
-    let mut config = re_init_cfddns(None);
+    let cli = Cli::parse();
+    let config = match Config::load(&cli.config) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let client = match CfClient::from_config(&config) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Failed to create the api client: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(&cli, config, client),
+        Command::Once => {
+            if let Err(e) = sync(&cli, &client, &config) {
+                eprintln!("Failed to sync: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Plan => {
+            let plan = reconcile::plan(
+                &client,
+                &config,
+                &lookup_ips(&config, &cli.state_dir),
+                &ManagedZones::load(&cli.state_dir),
+            );
+            print_plan(&plan, cli.json);
+            // 没能读取全部状态时计划不完整
+            if !plan.errors.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Command::Cleanup => {
+            let mut owner = Owner::new(&config);
+            let result = plan_old_dns_records(
+                &client,
+                &config,
+                &mut owner,
+                &ManagedZones::load(&cli.state_dir),
+            )
+            .and_then(|operations| {
+                let plan = Plan {
+                    operations,
+                    ..Default::default()
+                };
+                if cli.dry_run {
+                    print_plan(&plan, cli.json);
+                    Ok(())
+                } else {
+                    plan.apply(&client, &owner)
+                }
+            });
+            if let Err(e) = result {
+                eprintln!("Failed to clean up old dns records: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::List => match list_device_records(&client, &config) {
+            Ok(records) if cli.json => {
+                let records: Vec<_> = records.into_iter().map(|(_, record)| record).collect();
+                match serde_json::to_string_pretty(&records) {
+                    Ok(records) => println!("{}", records),
+                    Err(e) => eprintln!("Failed to serialize records: {:?}", e),
+                }
+            }
+            Ok(records) => {
+                for (zone, record) in records {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        zone, record.type_field, record.name, record.content, record.id
+                    );
+                }
+            }
+            Err(e) => eprintln!("Failed to list dns records: {:?}", e),
+        },
+        Command::CheckConfig => println!("{} is valid", cli.config.display()),
+    }
+}
+
+/// 同步一次，dry-run 时只打印会执行的操作
+fn sync(cli: &Cli, client: &CfClient, config: &Config) -> anyhow::Result<()> {
+    if cli.dry_run {
+        let plan = reconcile::plan(
+            client,
+            config,
+            &lookup_ips(config, &cli.state_dir),
+            &ManagedZones::load(&cli.state_dir),
+        );
+        print_plan(&plan, cli.json);
+        if !plan.errors.is_empty() {
+            anyhow::bail!("failed to read the records of {} zones", plan.errors.len());
+        }
+        Ok(())
+    } else {
+        sync_dns_records(client, config, &cli.state_dir)
+    }
+}
+
+fn run(cli: &Cli, mut config: Config, mut client: CfClient) {
+    // 启动和配置变更时同样只修改和已有记录不一致的部分
+    if let Err(e) = sync(cli, &client, &config) {
+        eprintln!("Failed to sync: {:?}", e);
+    }
     // generate a hash code of the config
     let mut config_hash = config.hash_code();
+    let mut watcher = None;
 
     loop {
-        sleep(Duration::from_secs(config.interval));
-        config = Config::load();
+        update_watcher(&mut watcher, cli.watch || config.watch);
+        if wait_for_change(watcher.as_ref(), Duration::from_secs(config.interval)) {
+            println!("Interface addresses changed, syncing now");
+        }
+        // 配置有误时继续使用上一次正确的配置
+        match Config::load(&cli.config) {
+            Ok(new_config) => config = new_config,
+            Err(e) => eprintln!("Failed to reload config, keep the last good one: {}", e),
+        }
         let new_config_hash = config.hash_code();
 
-        if new_config_hash == config_hash {
-            let ip_differences = get_ip_difference();
-            apply_ip_differences(ip_differences, &config);
-        } else {
-            config = re_init_cfddns(Some(config));
-            config_hash = new_config_hash;
+        // token 或 api_url 可能变了
+        if new_config_hash != config_hash {
+            match CfClient::from_config(&config) {
+                Ok(new_client) => client = new_client,
+                Err(e) => eprintln!(
+                    "Failed to create the api client, keep the last one: {:?}",
+                    e
+                ),
+            }
+        }
+        if let Err(e) = sync(cli, &client, &config) {
+            eprintln!("Failed to sync: {:?}", e);
+        }
+        config_hash = new_config_hash;
+    }
+}
+
+/// 按配置创建或关闭地址变化的监听，创建失败时退回到定时同步
+fn update_watcher(watcher: &mut Option<AddrWatcher>, enabled: bool) {
+    if !enabled {
+        *watcher = None;
+        return;
+    }
+    if watcher.is_none() {
+        match AddrWatcher::new() {
+            Ok(new_watcher) => *watcher = Some(new_watcher),
+            Err(e) => eprintln!(
+                "Failed to watch address changes, fall back to polling: {}",
+                e
+            ),
         }
     }
 }
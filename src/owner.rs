@@ -0,0 +1,329 @@
+use anyhow::Result;
+
+use crate::{
+    cf_api::{
+        list_dns::{list_dns_records, DnsRecordFilter, DnsRecordResult},
+        CfClient, CfDnsRecord,
+    },
+    config::{Config, OwnershipStrategy},
+};
+
+/// heritage TXT 记录的名称前缀
+pub const HERITAGE_PREFIX: &str = "_cf-ddns.";
+
+/// 本设备的 comment 标记，设备名中不允许出现 `[` 和 `]`，
+/// 所以 `[home]` 不会匹配到 `[home2]` 的记录
+pub fn device_prefix(device: &str) -> String {
+    format!("[{}]", device)
+}
+
+/// 本设备创建的记录的 comment
+pub fn device_comment(device: &str, comment: Option<&str>) -> String {
+    format!("{} {}", device_prefix(device), comment.unwrap_or_default())
+}
+
+/// 本设备的标签，Cloudflare 的标签格式为 `name:value`
+pub fn device_tag(device: &str) -> String {
+    format!("cf-ddns:device={}", device)
+}
+
+/// 记录对应的 heritage TXT 记录名，通配符记录的 `*` 换成 `_wildcard`
+pub fn heritage_name(name: &str) -> String {
+    match name.strip_prefix("*.") {
+        Some(rest) => format!("{}_wildcard.{}", HERITAGE_PREFIX, rest),
+        None => format!("{}{}", HERITAGE_PREFIX, name),
+    }
+}
+
+/// heritage TXT 记录的内容
+pub fn heritage_content(device: &str, type_field: &str) -> String {
+    format!("heritage=cf-ddns,device={},type={}", device, type_field)
+}
+
+/// comment 中是否有本设备的标记，标记前后需要是空白或者 comment 的开头结尾，
+/// 这样在面板中修改 comment 的其他部分不会影响归属。设备名中可以有空格
+pub fn is_managed_by(comment: Option<&str>, device: &str) -> bool {
+    let prefix = device_prefix(device);
+    comment.is_some_and(|comment| {
+        comment.match_indices(&prefix).any(|(start, _)| {
+            let before = comment[..start].chars().next_back();
+            let after = comment[start + prefix.len()..].chars().next();
+            before.is_none_or(char::is_whitespace) && after.is_none_or(char::is_whitespace)
+        })
+    })
+}
+
+/// 判断记录是否属于本设备，所有修改和删除记录的操作都必须先经过这个判断
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Owner {
+    pub device: String,
+    pub strategy: OwnershipStrategy,
+    pub migrate_from: Vec<OwnershipStrategy>,
+    /// 已拉取的本设备的 heritage TXT 记录
+    pub heritage: Vec<DnsRecordResult>,
+}
+
+impl Owner {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            device: config.device.clone(),
+            strategy: config.ownership,
+            migrate_from: config.ownership_migrate_from.clone(),
+            heritage: Vec::new(),
+        }
+    }
+
+    /// 当前使用或者正在迁移的标记方式
+    pub fn uses(&self, strategy: OwnershipStrategy) -> bool {
+        self.strategy == strategy || self.migrate_from.contains(&strategy)
+    }
+
+    /// 是否为本设备的 heritage TXT 记录
+    pub fn is_heritage(&self, record: &DnsRecordResult) -> bool {
+        record.type_field == "TXT"
+            && record.name.starts_with(HERITAGE_PREFIX)
+            && record
+                .content
+                .trim_matches('"')
+                .starts_with(&heritage_content(&self.device, ""))
+    }
+
+    /// 记录是否属于本设备
+    pub fn is_managed(&self, record: &DnsRecordResult) -> bool {
+        if self.is_heritage(record) {
+            return true;
+        }
+        let comment = self.uses(OwnershipStrategy::Comment)
+            && is_managed_by(record.comment.as_deref(), &self.device);
+        let tag =
+            self.uses(OwnershipStrategy::Tag) && record.tags.contains(&device_tag(&self.device));
+        let txt = self.uses(OwnershipStrategy::Txt)
+            && self.heritage.iter().any(|heritage| {
+                heritage.name == heritage_name(&record.name)
+                    && heritage.content.trim_matches('"')
+                        == heritage_content(&self.device, &record.type_field)
+            });
+        comment || tag || txt
+    }
+
+    /// 给期望的记录加上当前方式的标记，旧方式的标记会在同步时被去掉
+    pub fn mark(&self, record: &mut CfDnsRecord, comment: Option<&str>) {
+        match self.strategy {
+            OwnershipStrategy::Comment => {
+                record.comment = Some(device_comment(&self.device, comment));
+            }
+            OwnershipStrategy::Tag => {
+                record.comment = Some(comment.unwrap_or_default().to_string());
+                record.tags.push(device_tag(&self.device));
+            }
+            OwnershipStrategy::Txt => {
+                record.comment = Some(comment.unwrap_or_default().to_string());
+            }
+        }
+    }
+
+    /// 期望记录对应的 heritage TXT 记录，只在使用 txt 方式时生成
+    pub fn heritage_records(&self, desired: &[CfDnsRecord]) -> Vec<CfDnsRecord> {
+        if self.strategy != OwnershipStrategy::Txt {
+            return Vec::new();
+        }
+        let mut records: Vec<CfDnsRecord> = Vec::new();
+        for record in desired {
+            let heritage = CfDnsRecord {
+                name: heritage_name(&record.name),
+                type_field: "TXT".to_string(),
+                content: heritage_content(&self.device, &record.type_field),
+                comment: Some(String::new()),
+                proxied: Some(false),
+                ..Default::default()
+            };
+            if !records.contains(&heritage) {
+                records.push(heritage);
+            }
+        }
+        records
+    }
+
+    /// 拉取 zone 中本设备的 heritage TXT 记录，返回新拉取到的记录
+    pub fn load_heritage(
+        &mut self,
+        client: &CfClient,
+        zone_id: &str,
+    ) -> Result<Vec<DnsRecordResult>> {
+        if !self.uses(OwnershipStrategy::Txt) {
+            return Ok(Vec::new());
+        }
+        let filter = DnsRecordFilter::default()
+            .type_field("TXT")
+            .name_startswith(HERITAGE_PREFIX)
+            .content_contains(&heritage_content(&self.device, ""));
+        let heritage: Vec<_> = list_dns_records(client, zone_id, &filter)?
+            .into_iter()
+            .filter(|record| self.is_heritage(record))
+            .collect();
+        for record in &heritage {
+            if !self.heritage.iter().any(|r| r.id == record.id) {
+                self.heritage.push(record.clone());
+            }
+        }
+        Ok(heritage)
+    }
+
+    /// 拉取 zone 中本设备的所有记录，包括 heritage TXT 记录
+    pub fn list_records(
+        &mut self,
+        client: &CfClient,
+        zone_id: &str,
+    ) -> Result<Vec<DnsRecordResult>> {
+        let heritage = self.load_heritage(client, zone_id)?;
+        let mut filters = Vec::new();
+        if self.uses(OwnershipStrategy::Comment) {
+            filters.push(DnsRecordFilter::default().comment_contains(&device_prefix(&self.device)));
+        }
+        if self.uses(OwnershipStrategy::Tag) {
+            filters.push(DnsRecordFilter::default().tag(&device_tag(&self.device)));
+        }
+        let mut records = heritage.clone();
+        for record in &heritage {
+            if let Some(name) = record.name.strip_prefix(HERITAGE_PREFIX) {
+                let name = match name.strip_prefix("_wildcard.") {
+                    Some(rest) => format!("*.{}", rest),
+                    None => name.to_string(),
+                };
+                filters.push(DnsRecordFilter::default().name(&name));
+            }
+        }
+        for filter in filters {
+            for record in list_dns_records(client, zone_id, &filter)? {
+                if self.is_managed(&record) && !records.iter().any(|r| r.id == record.id) {
+                    records.push(record);
+                }
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(name: &str, type_field: &str, content: &str) -> DnsRecordResult {
+        DnsRecordResult {
+            id: name.to_string(),
+            name: name.to_string(),
+            type_field: type_field.to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_is_managed_by() {
+        // 本设备的记录
+        assert!(is_managed_by(Some("[home] "), "home"));
+        assert!(is_managed_by(Some("[home] my comment"), "home"));
+        assert!(is_managed_by(
+            Some(&device_comment("home", Some("x"))),
+            "home"
+        ));
+        // 在面板中修改过 comment 的其他部分
+        assert!(is_managed_by(Some("router [home] edited"), "home"));
+        // 设备名中有空格
+        assert!(is_managed_by(Some("[my device] nas"), "my device"));
+        assert!(!is_managed_by(Some("[my device] nas"), "device"));
+        assert!(!is_managed_by(Some("x[home] "), "home"));
+        // 手动添加的记录
+        assert!(!is_managed_by(None, "home"));
+        assert!(!is_managed_by(Some(""), "home"));
+        assert!(!is_managed_by(Some("home server"), "home"));
+        assert!(!is_managed_by(Some("[home]x"), "home"));
+        // 其他设备的记录
+        assert!(!is_managed_by(Some("[office] "), "home"));
+        assert!(!is_managed_by(Some("[home2] "), "home"));
+        assert!(!is_managed_by(Some("[hom] "), "home"));
+    }
+
+    #[test]
+    fn test_owner_strategies() {
+        let owner = |strategy, migrate_from: &[OwnershipStrategy]| Owner {
+            device: "home".to_string(),
+            strategy,
+            migrate_from: migrate_from.to_vec(),
+            heritage: vec![
+                record(
+                    "_cf-ddns.www.example.com",
+                    "TXT",
+                    "heritage=cf-ddns,device=home,type=A",
+                ),
+                record(
+                    "_cf-ddns._wildcard.example.com",
+                    "TXT",
+                    "\"heritage=cf-ddns,device=home,type=AAAA\"",
+                ),
+            ],
+        };
+        let mut commented = record("www.example.com", "A", "203.0.113.7");
+        commented.comment = Some("[home] ".to_string());
+        let mut tagged = record("www.example.com", "A", "203.0.113.7");
+        tagged.tags = vec![device_tag("home")];
+        let heritage = record("www.example.com", "A", "203.0.113.7");
+        let wildcard = record("*.example.com", "AAAA", "2001:db8::1");
+        let manual = record("www.example.com", "AAAA", "2001:db8::1");
+        let mut other = record("office.example.com", "A", "203.0.113.7");
+        other.comment = Some("[office] ".to_string());
+        other.tags = vec![device_tag("office")];
+
+        let comment = owner(OwnershipStrategy::Comment, &[]);
+        assert!(comment.is_managed(&commented));
+        assert!(!comment.is_managed(&tagged));
+        assert!(!comment.is_managed(&heritage));
+
+        let tag = owner(OwnershipStrategy::Tag, &[]);
+        assert!(tag.is_managed(&tagged));
+        assert!(!tag.is_managed(&commented));
+
+        let txt = owner(OwnershipStrategy::Txt, &[]);
+        assert!(txt.is_managed(&heritage));
+        assert!(txt.is_managed(&wildcard));
+        assert!(txt.is_heritage(&txt.heritage[0]));
+        // heritage 只对应 A 记录
+        assert!(!txt.is_managed(&manual));
+
+        // 迁移时两种标记都认
+        let migrating = owner(OwnershipStrategy::Tag, &[OwnershipStrategy::Comment]);
+        assert!(migrating.is_managed(&commented));
+        assert!(migrating.is_managed(&tagged));
+
+        for owner in [comment, tag, txt, migrating] {
+            assert!(!owner.is_managed(&other));
+            assert!(!owner.is_heritage(&record(
+                "_cf-ddns.www.example.com",
+                "TXT",
+                "heritage=cf-ddns,device=office,type=A"
+            )));
+        }
+    }
+
+    #[test]
+    fn test_owner_mark() {
+        let mut owner = Owner {
+            device: "home".to_string(),
+            ..Default::default()
+        };
+        let mut record = CfDnsRecord {
+            name: "*.example.com".to_string(),
+            type_field: "A".to_string(),
+            ..Default::default()
+        };
+        owner.mark(&mut record, Some("nas"));
+        assert_eq!(record.comment.as_deref(), Some("[home] nas"));
+        assert!(owner.heritage_records(&[record.clone()]).is_empty());
+
+        owner.strategy = OwnershipStrategy::Txt;
+        let heritage = owner.heritage_records(&[record.clone(), record]);
+        assert_eq!(heritage.len(), 1);
+        assert_eq!(heritage[0].name, "_cf-ddns._wildcard.example.com");
+        assert_eq!(heritage[0].content, "heritage=cf-ddns,device=home,type=A");
+    }
+}
@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_ENV: &str = "CF_DDNS_CONFIG";
+pub const STATE_DIR_ENV: &str = "CF_DDNS_STATE_DIR";
+
+const APP_NAME: &str = "cf-ddns";
+const CONFIG_FILE: &str = "config.toml";
+
+/// 默认配置文件路径，按顺序取第一个存在的:
+/// 1. 当前目录的 config.toml (兼容旧版本)
+/// 2. $CONFIGURATION_DIRECTORY/config.toml (systemd ConfigurationDirectory=)
+/// 3. $XDG_CONFIG_HOME/cf-ddns/config.toml 或 ~/.config/cf-ddns/config.toml
+/// 4. /etc/cf-ddns/config.toml (都不存在时也使用这个)
+pub fn default_config_path() -> PathBuf {
+    config_path_from(|key| std::env::var(key).ok(), |path| path.exists())
+}
+
+/// 默认状态目录:
+/// 1. $STATE_DIRECTORY (systemd StateDirectory=)
+/// 2. $XDG_STATE_HOME/cf-ddns 或 ~/.local/state/cf-ddns
+/// 3. /var/lib/cf-ddns
+pub fn default_state_dir() -> PathBuf {
+    state_dir_from(|key| std::env::var(key).ok())
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.is_empty())
+}
+
+/// $XDG_xxx_HOME, 未设置时使用 $HOME 下的默认目录
+fn xdg_dir(env: &impl Fn(&str) -> Option<String>, key: &str, fallback: &str) -> Option<PathBuf> {
+    if let Some(dir) = non_empty(env(key)) {
+        return Some(PathBuf::from(dir));
+    }
+    // DynamicUser 等场景下 HOME 可能是 /
+    non_empty(env("HOME"))
+        .filter(|home| home != "/")
+        .map(|home| Path::new(&home).join(fallback))
+}
+
+fn config_path_from(
+    env: impl Fn(&str) -> Option<String>,
+    exists: impl Fn(&Path) -> bool,
+) -> PathBuf {
+    let system = Path::new("/etc").join(APP_NAME).join(CONFIG_FILE);
+    let candidates = [
+        Some(PathBuf::from(CONFIG_FILE)),
+        non_empty(env("CONFIGURATION_DIRECTORY"))
+            // 可能配置了多个目录，用 : 分隔
+            .and_then(|dirs| {
+                dirs.split(':')
+                    .next()
+                    .map(|dir| Path::new(dir).join(CONFIG_FILE))
+            }),
+        xdg_dir(&env, "XDG_CONFIG_HOME", ".config").map(|dir| dir.join(APP_NAME).join(CONFIG_FILE)),
+    ];
+    candidates
+        .into_iter()
+        .flatten()
+        .find(|path| exists(path))
+        .unwrap_or(system)
+}
+
+fn state_dir_from(env: impl Fn(&str) -> Option<String>) -> PathBuf {
+    if let Some(dirs) = non_empty(env("STATE_DIRECTORY")) {
+        if let Some(dir) = dirs.split(':').next() {
+            return PathBuf::from(dir);
+        }
+    }
+    xdg_dir(&env, "XDG_STATE_HOME", ".local/state")
+        .map(|dir| dir.join(APP_NAME))
+        .unwrap_or_else(|| Path::new("/var/lib").join(APP_NAME))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |key| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn test_default_paths() {
+        // 当前目录有配置文件时优先使用
+        assert_eq!(
+            config_path_from(env(&[("HOME", "/home/a")]), |_| true),
+            PathBuf::from("config.toml")
+        );
+        assert_eq!(
+            config_path_from(env(&[("HOME", "/home/a")]), |p| p.starts_with("/home/a")),
+            PathBuf::from("/home/a/.config/cf-ddns/config.toml")
+        );
+        assert_eq!(
+            config_path_from(env(&[]), |_| false),
+            PathBuf::from("/etc/cf-ddns/config.toml")
+        );
+
+        assert_eq!(
+            state_dir_from(env(&[
+                ("STATE_DIRECTORY", "/var/lib/cf-ddns2"),
+                ("HOME", "/")
+            ])),
+            PathBuf::from("/var/lib/cf-ddns2")
+        );
+        assert_eq!(
+            state_dir_from(env(&[("XDG_STATE_HOME", "/tmp/state")])),
+            PathBuf::from("/tmp/state/cf-ddns")
+        );
+        assert_eq!(
+            state_dir_from(env(&[("HOME", "/")])),
+            PathBuf::from("/var/lib/cf-ddns")
+        );
+    }
+}
@@ -0,0 +1,1373 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cf_api::{
+        create_dns::create_dns_record,
+        delete_dns::delete_dns_record,
+        list_dns::{list_dns_records, DnsRecordFilter, DnsRecordResult},
+        update_dns::{update_dns_record, DnsRecordPatch},
+        CfClient, CfDnsRecord, RecordData, SvcbData, UriData,
+    },
+    config::{Config, DnsRecordConfig, DnsType, PrunePolicy, ZoneConfig},
+    ip::{provider::IpFamily, Ips},
+    owner::{self, Owner},
+    template,
+};
+
+/// 一次同步需要执行的操作
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Operation {
+    Create {
+        zone: String,
+        zone_id: String,
+        record: CfDnsRecord,
+    },
+    Update {
+        zone: String,
+        zone_id: String,
+        current: DnsRecordResult,
+        patch: DnsRecordPatch,
+    },
+    Delete {
+        zone: String,
+        zone_id: String,
+        record: DnsRecordResult,
+        /// 和保留下来的这条记录重复
+        #[serde(skip_serializing_if = "Option::is_none")]
+        duplicate_of: Option<String>,
+    },
+}
+
+impl Operation {
+    /// 修改或删除的已有记录，创建时为 None
+    pub fn existing(&self) -> Option<&DnsRecordResult> {
+        match self {
+            Operation::Create { .. } => None,
+            Operation::Update { current, .. } => Some(current),
+            Operation::Delete { record, .. } => Some(record),
+        }
+    }
+
+    pub fn apply(&self, client: &CfClient) -> Result<()> {
+        match self {
+            Operation::Create {
+                zone_id, record, ..
+            } => create_dns_record(client, zone_id, record.clone()),
+            Operation::Update {
+                zone_id,
+                current,
+                patch,
+                ..
+            } => update_dns_record(client, zone_id, &current.id, patch),
+            Operation::Delete {
+                zone_id, record, ..
+            } => delete_dns_record(client, zone_id, &record.id),
+        }
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Create { zone, record, .. } => {
+                write!(
+                    f,
+                    "+ create {} {} {} (zone: {})",
+                    record.type_field,
+                    record.name,
+                    record.display_content(),
+                    zone
+                )
+            }
+            Operation::Update {
+                zone,
+                current,
+                patch,
+                ..
+            } => {
+                write!(
+                    f,
+                    "~ update {} {} {} (zone: {}, id: {})",
+                    current.type_field, current.name, current.content, zone, current.id
+                )?;
+                if let Some(content) = &patch.content {
+                    write!(f, "\n    content: {} -> {}", current.content, content)?;
+                }
+                if let Some(data) = &patch.data {
+                    write!(f, "\n    data: {} -> {}", current.content, data)?;
+                }
+                if let Some(priority) = patch.priority {
+                    write!(
+                        f,
+                        "\n    priority: {} -> {}",
+                        current.priority.unwrap_or_default(),
+                        priority
+                    )?;
+                }
+                if let Some(comment) = &patch.comment {
+                    write!(
+                        f,
+                        "\n    comment: {:?} -> {:?}",
+                        current.comment.as_deref().unwrap_or_default(),
+                        comment
+                    )?;
+                }
+                if let Some(proxied) = patch.proxied {
+                    write!(f, "\n    proxied: {} -> {}", current.proxied, proxied)?;
+                }
+                if let Some(ttl) = patch.ttl {
+                    write!(f, "\n    ttl: {} -> {}", current.ttl, ttl)?;
+                }
+                if let Some(tags) = &patch.tags {
+                    write!(f, "\n    tags: {:?} -> {:?}", current.tags, tags)?;
+                }
+                Ok(())
+            }
+            Operation::Delete {
+                zone,
+                record,
+                duplicate_of,
+                ..
+            } => {
+                write!(
+                    f,
+                    "- delete {} {} {} (zone: {}, id: {}",
+                    record.type_field, record.name, record.content, zone, record.id
+                )?;
+                if let Some(kept) = duplicate_of {
+                    write!(f, ", duplicate of {}", kept)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Plan {
+    pub operations: Vec<Operation>,
+    /// 无法读取实际状态的 zone 和原因，这些 zone 的操作不在计划中
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// 统计创建、修改、删除的数量
+    pub fn summary(&self) -> (usize, usize, usize) {
+        self.operations
+            .iter()
+            .fold((0, 0, 0), |(create, update, delete), op| match op {
+                Operation::Create { .. } => (create + 1, update, delete),
+                Operation::Update { .. } => (create, update + 1, delete),
+                Operation::Delete { .. } => (create, update, delete + 1),
+            })
+    }
+
+    /// 删除的重复记录数量
+    pub fn duplicates(&self) -> usize {
+        self.operations
+            .iter()
+            .filter(|op| {
+                matches!(
+                    op,
+                    Operation::Delete {
+                        duplicate_of: Some(_),
+                        ..
+                    }
+                )
+            })
+            .count()
+    }
+
+    /// 执行操作的顺序: 先创建 heritage 记录，再修改和创建，然后删除，最后删除 heritage 记录，
+    /// 这样记录在任何时候都带有本设备的标记
+    fn ordered(&self, owner: &Owner) -> Vec<&Operation> {
+        let rank = |op: &Operation| match op {
+            Operation::Create { record, .. } if record.name.starts_with(owner::HERITAGE_PREFIX) => {
+                0
+            }
+            Operation::Update { .. } => 1,
+            Operation::Create { .. } => 2,
+            Operation::Delete { record, .. } if owner.is_heritage(record) => 4,
+            Operation::Delete { .. } => 3,
+        };
+        let mut ordered: Vec<_> = self.operations.iter().collect();
+        ordered.sort_by_key(|op| rank(op));
+        ordered
+    }
+
+    /// 按 ordered 的顺序执行，先修改和创建再删除，避免域名在同步过程中没有解析。
+    /// 不属于本设备的记录不会被修改或删除，有操作失败的记录会保留它的 heritage 记录
+    pub fn apply(&self, client: &CfClient, owner: &Owner) -> Result<()> {
+        if self.duplicates() > 0 {
+            println!(
+                "Found {} duplicate records, removing them",
+                self.duplicates()
+            );
+        }
+        // heritage 记录没有创建的记录名，这些记录的修改和删除会被跳过
+        let mut failed_heritage = Vec::new();
+        // 有操作失败的记录名对应的 heritage 记录，这些 heritage 记录不能删除
+        let mut protected_heritage = Vec::new();
+        let mut failed = 0;
+        for operation in self.ordered(owner) {
+            if let Some(record) = operation.existing() {
+                if !owner.is_managed(record) {
+                    eprintln!(
+                        "Refuse to apply operation on a record not managed by {}: {:?}",
+                        owner.device, operation
+                    );
+                    failed += 1;
+                    continue;
+                }
+                if failed_heritage.contains(&owner::heritage_name(&record.name)) {
+                    eprintln!(
+                        "Skip operation until the heritage record of {} is created: {:?}",
+                        record.name, operation
+                    );
+                    protected_heritage.push(owner::heritage_name(&record.name));
+                    failed += 1;
+                    continue;
+                }
+                if owner.is_heritage(record) && protected_heritage.contains(&record.name) {
+                    eprintln!(
+                        "Keep the heritage record because an operation on its record failed: {:?}",
+                        operation
+                    );
+                    failed += 1;
+                    continue;
+                }
+            }
+            match operation.apply(client) {
+                Ok(_) => println!("Succeed to apply operation: {:?}", operation),
+                Err(e) => {
+                    eprintln!("Failed to apply operation {:?}: {:?}", operation, e);
+                    failed += 1;
+                    match operation {
+                        Operation::Create { record, .. }
+                            if record.name.starts_with(owner::HERITAGE_PREFIX) =>
+                        {
+                            failed_heritage.push(record.name.clone());
+                        }
+                        Operation::Create { record, .. } => {
+                            protected_heritage.push(owner::heritage_name(&record.name));
+                        }
+                        Operation::Update {
+                            current: record, ..
+                        }
+                        | Operation::Delete { record, .. } => {
+                            protected_heritage.push(owner::heritage_name(&record.name));
+                        }
+                    }
+                }
+            }
+        }
+        if failed > 0 {
+            anyhow::bail!("{} of {} operations failed", failed, self.operations.len());
+        }
+        Ok(())
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for error in &self.errors {
+            writeln!(f, "! failed to read {}", error)?;
+        }
+        if !self.errors.is_empty() && self.is_empty() {
+            return write!(f, "No changes planned, but the state is incomplete.");
+        }
+        if self.is_empty() {
+            return write!(f, "No changes. DNS records are up to date.");
+        }
+        for operation in &self.operations {
+            writeln!(f, "{}", operation)?;
+        }
+        let (create, update, delete) = self.summary();
+        write!(
+            f,
+            "Plan: {} to create, {} to update, {} to delete",
+            create, update, delete
+        )?;
+        match self.duplicates() {
+            0 => write!(f, "."),
+            duplicates => write!(f, " ({} duplicates).", duplicates),
+        }?;
+        if !self.errors.is_empty() {
+            write!(f, " The state of {} zones is missing.", self.errors.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// 由本程序管理的记录类型
+const MANAGED_TYPES: [&str; 7] = ["A", "AAAA", "TXT", "HTTPS", "SVCB", "CNAME", "URI"];
+
+/// 根据配置和当前ip计算 zone 中应当存在的记录
+pub fn desired_records(config: &Config, zone_config: &ZoneConfig, ips: &Ips) -> Vec<CfDnsRecord> {
+    let owner = Owner::new(config);
+    let mut desired: Vec<CfDnsRecord> = Vec::new();
+    for record_config in &zone_config.records {
+        let families =
+            [IpFamily::V4, IpFamily::V6].map(|family| ips.for_record(record_config, family));
+        let records: Vec<_> = if record_config.dns_type.is_address() {
+            families
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter(|ip| record_config.dns_type.related(ip))
+                .map(|ip| CfDnsRecord::create(ip, record_config))
+                .collect()
+        } else {
+            // 合并的记录需要用到的协议族的地址都确定后才能生成
+            let [v4, v6] = [IpFamily::V4, IpFamily::V6].map(|family| {
+                if uses_family(record_config, family) {
+                    ips.for_record(record_config, family)
+                } else {
+                    Some(Vec::new())
+                }
+            });
+            match (v4, v6) {
+                (Some(v4), Some(v6)) => combined_record(&config.device, record_config, &v4, &v6)
+                    .into_iter()
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+        for mut record in records {
+            record.name = record_config.fqdn(&zone_config.name);
+            owner.mark(&mut record, record_config.comment.as_deref());
+            // 同名同类型同内容的记录只保留第一条配置
+            if !desired.iter().any(|d| {
+                d.name == record.name
+                    && d.type_field == record.type_field
+                    && d.content == record.content
+                    && d.data == record.data
+                    && d.priority == record.priority
+            }) {
+                desired.push(record);
+            }
+        }
+    }
+    let heritage = owner.heritage_records(&desired);
+    desired.extend(heritage);
+    desired
+}
+
+/// 记录的内容是否用到某个协议族的地址，模板只用到其中引用的变量
+fn uses_family(record_config: &DnsRecordConfig, family: IpFamily) -> bool {
+    match record_config.content.template() {
+        Some(t) => {
+            let name = match family {
+                IpFamily::V4 => "ipv4",
+                IpFamily::V6 => "ipv6",
+            };
+            template::variables(t).contains(&name)
+        }
+        None => true,
+    }
+}
+
+/// 把所有地址合并到一条 TXT、HTTPS 或 SVCB 记录中，或者渲染 content 模板作为 TXT、CNAME 的内容或 URI 的目标，
+/// 需要的地址一个都没有时返回 None
+fn combined_record(
+    device: &str,
+    record_config: &DnsRecordConfig,
+    v4: &[IpAddr],
+    v6: &[IpAddr],
+) -> Option<CfDnsRecord> {
+    let uses_ips = match record_config.content.template() {
+        Some(t) => template::variables(t)
+            .iter()
+            .any(|name| *name == "ipv4" || *name == "ipv6"),
+        None => true,
+    };
+    if uses_ips && v4.is_empty() && v6.is_empty() {
+        return None;
+    }
+    let join = |ips: &[IpAddr], prefix: &str, sep: &str| {
+        ips.iter()
+            .map(|ip| format!("{}{}", prefix, ip))
+            .collect::<Vec<_>>()
+            .join(sep)
+    };
+    let mut record = CfDnsRecord {
+        name: record_config.name.clone(),
+        type_field: record_config.dns_type.record_types()[0].to_string(),
+        comment: record_config.comment.clone(),
+        // 只有 CNAME 可以开启代理，TXT、HTTPS、SVCB 和 URI 记录总是不代理
+        proxied: Some(record_config.dns_type == DnsType::Cname && record_config.proxied),
+        tags: record_config.tags.clone(),
+        ttl: record_config.ttl,
+        ..Default::default()
+    };
+    if let Some(t) = record_config.content.template() {
+        let ips: Vec<IpAddr> = v4.iter().chain(v6).copied().collect();
+        let rendered = template::render_without_timestamp(t, device, &ips);
+        if record_config.dns_type == DnsType::Uri {
+            record.data = Some(RecordData::Uri(UriData {
+                weight: record_config.weight.unwrap_or(1),
+                target: template::fill_timestamp(&rendered),
+            }));
+            record.priority = Some(record_config.priority.unwrap_or(1));
+        } else {
+            record.content = template::fill_timestamp(&rendered);
+        }
+        record.template = Some(rendered);
+        return Some(record);
+    }
+    match record_config.dns_type {
+        DnsType::Txt => {
+            record.content = [join(v4, "", " "), join(v6, "", " ")]
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+        DnsType::Spf => {
+            let mechanisms: Vec<_> = [join(v4, "ip4:", " "), join(v6, "ip6:", " ")]
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .collect();
+            record.content = format!("v=spf1 {} ~all", mechanisms.join(" "));
+        }
+        DnsType::Https | DnsType::Svcb => {
+            let mut value: Vec<String> = record_config.params.iter().cloned().collect();
+            if !v4.is_empty() {
+                value.push(format!("ipv4hint=\"{}\"", join(v4, "", ",")));
+            }
+            if !v6.is_empty() {
+                value.push(format!("ipv6hint=\"{}\"", join(v6, "", ",")));
+            }
+            record.data = Some(RecordData::Svcb(SvcbData {
+                priority: record_config.priority.unwrap_or(1),
+                target: record_config
+                    .target
+                    .clone()
+                    .unwrap_or_else(|| ".".to_string()),
+                value: value.join(" "),
+            }));
+        }
+        DnsType::All | DnsType::V4 | DnsType::V6 | DnsType::Cname | DnsType::Uri => return None,
+    }
+    Some(record)
+}
+
+/// 无法确定地址的记录名和类型，这些记录本次不做修改
+pub fn unresolved_records(zone_config: &ZoneConfig, ips: &Ips) -> Vec<(String, &'static str)> {
+    let mut unresolved = Vec::new();
+    for record_config in &zone_config.records {
+        let fqdn = record_config.fqdn(&zone_config.name);
+        for family in [IpFamily::V4, IpFamily::V6] {
+            if !uses_family(record_config, family)
+                || ips.for_record(record_config, family).is_some()
+            {
+                continue;
+            }
+            if record_config.dns_type.is_address() {
+                unresolved.push((fqdn.clone(), family.record_type()));
+            } else {
+                unresolved.extend(
+                    record_config
+                        .dns_type
+                        .record_types()
+                        .iter()
+                        .map(|t| (fqdn.clone(), *t)),
+                );
+            }
+        }
+    }
+    unresolved
+}
+
+/// 拉取 zone 中由本设备管理的、配置中记录名对应的记录，包括对应的 heritage TXT 记录
+pub fn actual_records(
+    client: &CfClient,
+    owner: &mut Owner,
+    zone_config: &ZoneConfig,
+    zone_id: &str,
+) -> Result<Vec<DnsRecordResult>> {
+    let heritage = owner.load_heritage(client, zone_id)?;
+    let names: BTreeSet<_> = zone_config
+        .records
+        .iter()
+        .map(|record| record.fqdn(&zone_config.name))
+        .collect();
+
+    let mut records = Vec::new();
+    for name in &names {
+        // 标记可能在 comment、标签或 heritage 记录中，只按名称过滤
+        let filter = DnsRecordFilter::default().name(name);
+        records.extend(
+            list_dns_records(client, zone_id, &filter)?
+                .into_iter()
+                .filter(|r| owner.is_managed(r))
+                .filter(|r| MANAGED_TYPES.contains(&r.type_field.as_str())),
+        );
+        records.extend(
+            heritage
+                .iter()
+                .filter(|r| r.name == owner::heritage_name(name))
+                .cloned(),
+        );
+    }
+    Ok(records)
+}
+
+/// 期望记录的内容是否和实际记录一致
+fn same_content(desired: &CfDnsRecord, actual: &DnsRecordResult) -> bool {
+    let actual_data = actual
+        .data
+        .clone()
+        .and_then(|actual| serde_json::from_value::<RecordData>(actual).ok());
+    match (&desired.data, &desired.template) {
+        // URI 记录的模板渲染为 data 中的目标
+        (Some(RecordData::Uri(data)), Some(t)) => match actual_data {
+            Some(RecordData::Uri(actual_uri)) => {
+                desired.priority == actual.priority
+                    && data.weight == actual_uri.weight
+                    && template::matches(t, &actual_uri.target)
+            }
+            _ => false,
+        },
+        (Some(data), _) => actual_data.as_ref() == Some(data),
+        (None, Some(t)) => template::matches(t, actual.content.trim_matches('"')),
+        // TXT 记录的内容可能被加上引号
+        (None, None) => desired.content.trim_matches('"') == actual.content.trim_matches('"'),
+    }
+}
+
+/// 两条实际记录的内容是否相同
+fn same_actual_content(a: &DnsRecordResult, b: &DnsRecordResult) -> bool {
+    a.content.trim_matches('"') == b.content.trim_matches('"')
+        && a.data == b.data
+        && a.priority == b.priority
+}
+
+/// 期望记录和实际记录之间需要修改的字段
+fn record_patch(desired: &CfDnsRecord, actual: &DnsRecordResult) -> DnsRecordPatch {
+    let mut patch = DnsRecordPatch::default();
+    if !same_content(desired, actual) {
+        match &desired.data {
+            Some(data) => {
+                patch.data = Some(data.clone());
+                patch.priority = desired.priority;
+            }
+            None => patch.content = Some(desired.content.clone()),
+        }
+    }
+    // 空的 comment 可能被返回为 null
+    if let Some(comment) = &desired.comment {
+        if comment.trim_end() != actual.comment.as_deref().unwrap_or_default().trim_end() {
+            patch.comment = Some(comment.clone());
+        }
+    }
+    if let Some(proxied) = desired.proxied {
+        if proxied != actual.proxied {
+            patch.proxied = Some(proxied);
+        }
+    }
+    // 开启代理后 ttl 固定为自动，不做比较
+    if let Some(ttl) = desired.ttl {
+        if !desired.proxied.unwrap_or_default() && ttl != actual.ttl {
+            patch.ttl = Some(ttl);
+        }
+    }
+    let desired_tags: BTreeSet<_> = desired.tags.iter().collect();
+    let actual_tags: BTreeSet<_> = actual.tags.iter().collect();
+    if desired_tags != actual_tags {
+        patch.tags = Some(desired.tags.clone());
+    }
+    patch
+}
+
+/// 比较单个 zone 的期望状态和实际状态，生成操作
+pub fn plan_zone(
+    zone: &str,
+    zone_id: &str,
+    desired: &[CfDnsRecord],
+    actual: &[DnsRecordResult],
+) -> Vec<Operation> {
+    let mut operations = Vec::new();
+
+    let keys: BTreeSet<_> = desired
+        .iter()
+        .map(|r| (r.name.as_str(), r.type_field.as_str()))
+        .chain(
+            actual
+                .iter()
+                .map(|r| (r.name.as_str(), r.type_field.as_str())),
+        )
+        .collect();
+
+    for (name, type_field) in keys {
+        let desired: Vec<_> = desired
+            .iter()
+            .filter(|r| r.name == name && r.type_field == type_field)
+            .collect();
+        let mut actual: Vec<_> = actual
+            .iter()
+            .filter(|r| r.name == name && r.type_field == type_field)
+            .collect();
+
+        // 内容相同的记录直接匹配，只修改其他字段
+        let mut unmatched = Vec::new();
+        let mut kept = Vec::new();
+        for desired in desired {
+            match actual.iter().position(|a| same_content(desired, a)) {
+                Some(pos) => {
+                    let current = actual.remove(pos);
+                    kept.push((desired, current));
+                    let patch = record_patch(desired, current);
+                    if !patch.is_empty() {
+                        operations.push(Operation::Update {
+                            zone: zone.to_string(),
+                            zone_id: zone_id.to_string(),
+                            current: current.clone(),
+                            patch,
+                        });
+                    }
+                }
+                None => unmatched.push(desired),
+            }
+        }
+
+        // 剩余的记录优先原地修改，保持记录 id 不变
+        let mut actual = actual.into_iter();
+        for desired in unmatched {
+            match actual.next() {
+                Some(current) => {
+                    kept.push((desired, current));
+                    operations.push(Operation::Update {
+                        zone: zone.to_string(),
+                        zone_id: zone_id.to_string(),
+                        current: current.clone(),
+                        patch: record_patch(desired, current),
+                    })
+                }
+                None => operations.push(Operation::Create {
+                    zone: zone.to_string(),
+                    zone_id: zone_id.to_string(),
+                    record: desired.clone(),
+                }),
+            }
+        }
+        // 多余的记录全部删除，之前删除失败或重启留下的重复记录也在其中
+        for record in actual {
+            let duplicate_of = kept
+                .iter()
+                .find(|(desired, current)| {
+                    same_content(desired, record) || same_actual_content(current, record)
+                })
+                .map(|(_, current)| current.id.clone());
+            operations.push(Operation::Delete {
+                zone: zone.to_string(),
+                zone_id: zone_id.to_string(),
+                record: record.clone(),
+                duplicate_of,
+            });
+        }
+    }
+
+    operations
+}
+
+/// 本设备在配置中已经去掉的记录名下的记录
+pub fn removed_records<'a>(
+    zone_config: &ZoneConfig,
+    zone_state: &'a ZoneState,
+) -> Vec<&'a DnsRecordResult> {
+    let names: BTreeSet<_> = zone_config
+        .records
+        .iter()
+        .map(|record| record.fqdn(&zone_config.name))
+        .flat_map(|name| [owner::heritage_name(&name), name])
+        .collect();
+    zone_state
+        .owned
+        .iter()
+        // 配置中的记录名下的记录已经在 actual 中比较过
+        .filter(|record| !zone_state.actual.iter().any(|r| r.id == record.id))
+        .filter(|record| !names.contains(&record.name))
+        .collect()
+}
+
+/// 按 prune 策略处理配置中已经去掉的记录名下的记录
+fn plan_prune(config: &Config, zone_config: &ZoneConfig, zone_state: &ZoneState) -> Vec<Operation> {
+    let removed = removed_records(zone_config, zone_state);
+    match config.prune {
+        PrunePolicy::Delete => removed
+            .into_iter()
+            .map(|record| Operation::Delete {
+                zone: zone_state.zone.clone(),
+                zone_id: zone_state.zone_id.clone(),
+                record: record.clone(),
+                duplicate_of: None,
+            })
+            .collect(),
+        PrunePolicy::Keep => Vec::new(),
+        PrunePolicy::Warn => {
+            for record in removed {
+                eprintln!(
+                    "Record {} {} {} (zone: {}, id: {}) is no longer in the config, keep it",
+                    record.type_field, record.name, record.content, zone_state.zone, record.id
+                );
+            }
+            Vec::new()
+        }
+    }
+}
+
+/// 单个 zone 中本设备管理的记录
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneState {
+    pub zone: String,
+    pub zone_id: String,
+    pub actual: Vec<DnsRecordResult>,
+    /// zone 中本设备的所有记录，用来找出配置中已经去掉的记录，prune 为 keep 时不拉取
+    pub owned: Vec<DnsRecordResult>,
+}
+
+const MANAGED_ZONES_FILE: &str = "zones.json";
+
+/// 本设备管理过的 zone, zone name -> zone id，保存在状态目录中，
+/// 用来找到已经从配置中去掉的 zone 里留下的记录
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManagedZones(pub BTreeMap<String, String>);
+
+impl ManagedZones {
+    pub fn path(state_dir: &Path) -> PathBuf {
+        state_dir.join(MANAGED_ZONES_FILE)
+    }
+
+    pub fn save(&self, state_dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(state_dir)?;
+        std::fs::write(Self::path(state_dir), serde_json::to_string(self)?)
+    }
+
+    pub fn load(state_dir: &Path) -> Self {
+        std::fs::read_to_string(Self::path(state_dir))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    /// 管理过但已经不在配置中的 zone
+    pub fn dropped<'a>(&'a self, config: &'a Config) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.0
+            .iter()
+            .filter(|(name, _)| !is_configured(config, name))
+            .map(|(name, zone_id)| (name.as_str(), zone_id.as_str()))
+    }
+
+    /// 记下本次同步读取到的 zone。已经去掉的 zone 中的记录删除成功或者没有记录后不再查看，
+    /// prune 为 warn 时一直保留以便继续提示，为 keep 时直接忘掉
+    pub fn update(&mut self, config: &Config, state: &[ZoneState], applied: bool) {
+        for zone_state in state {
+            let done =
+                zone_state.owned.is_empty() || (config.prune == PrunePolicy::Delete && applied);
+            if is_configured(config, &zone_state.zone) || !done {
+                self.0
+                    .insert(zone_state.zone.clone(), zone_state.zone_id.clone());
+            } else {
+                self.0.remove(&zone_state.zone);
+            }
+        }
+        if config.prune == PrunePolicy::Keep {
+            self.0.retain(|name, _| is_configured(config, name));
+        }
+    }
+}
+
+fn is_configured(config: &Config, zone: &str) -> bool {
+    config
+        .zones
+        .iter()
+        .any(|zone_config| zone_config.name.eq_ignore_ascii_case(zone))
+}
+
+/// 拉取所有配置的 zone 的实际状态，prune 不为 keep 时还会拉取已经去掉的 zone 中本设备的记录。
+/// 单个 zone 出错时跳过该 zone，并返回出错的 zone 和原因
+pub fn fetch_state(
+    client: &CfClient,
+    config: &Config,
+    owner: &mut Owner,
+    managed: &ManagedZones,
+) -> (Vec<ZoneState>, Vec<String>) {
+    let mut state = Vec::new();
+    let mut errors = Vec::new();
+    for zone_config in &config.zones {
+        match fetch_zone_state(client, config, owner, zone_config) {
+            Ok(zone_state) => state.push(zone_state),
+            Err(e) => errors.push(format!("zone {}: {:#}", zone_config.name, e)),
+        }
+    }
+    if config.prune != PrunePolicy::Keep {
+        for (zone, zone_id) in managed.dropped(config) {
+            match owner.list_records(client, zone_id) {
+                Ok(owned) => state.push(ZoneState {
+                    zone: zone.to_string(),
+                    zone_id: zone_id.to_string(),
+                    actual: Vec::new(),
+                    owned,
+                }),
+                Err(e) => errors.push(format!("zone {} (no longer in the config): {:#}", zone, e)),
+            }
+        }
+    }
+    (state, errors)
+}
+
+fn fetch_zone_state(
+    client: &CfClient,
+    config: &Config,
+    owner: &mut Owner,
+    zone_config: &ZoneConfig,
+) -> Result<ZoneState> {
+    let zone_id = client
+        .zone_id(zone_config)
+        .context("failed to get zone id")?;
+    let actual = actual_records(client, owner, zone_config, &zone_id)
+        .context("failed to list dns records")?;
+    let owned = match config.prune {
+        PrunePolicy::Keep => Vec::new(),
+        _ => owner
+            .list_records(client, &zone_id)
+            .context("failed to list dns records")?,
+    };
+    Ok(ZoneState {
+        zone: zone_config.name.clone(),
+        zone_id,
+        actual,
+        owned,
+    })
+}
+
+/// 根据已拉取的实际状态计算需要执行的操作
+pub fn plan_from_state(config: &Config, ips: &Ips, state: &[ZoneState]) -> Plan {
+    let mut plan = Plan::default();
+    for zone_state in state {
+        let Some(zone_config) = config.zones.iter().find(|z| z.name == zone_state.zone) else {
+            // 已经从配置中去掉的 zone，其中本设备的记录全部按 prune 策略处理
+            let zone_config = ZoneConfig {
+                name: zone_state.zone.clone(),
+                ..Default::default()
+            };
+            plan.operations
+                .extend(plan_prune(config, &zone_config, zone_state));
+            continue;
+        };
+        let desired = desired_records(config, zone_config, ips);
+        let unresolved = unresolved_records(zone_config, ips);
+        let actual: Vec<_> = zone_state
+            .actual
+            .iter()
+            .filter(|r| {
+                !unresolved.iter().any(|(name, type_field)| {
+                    (r.name == *name && r.type_field == *type_field)
+                        || (r.name == owner::heritage_name(name)
+                            && r.content.trim_matches('"')
+                                == owner::heritage_content(&config.device, type_field))
+                })
+            })
+            .cloned()
+            .collect();
+        plan.operations.extend(plan_zone(
+            &zone_state.zone,
+            &zone_state.zone_id,
+            &desired,
+            &actual,
+        ));
+        plan.operations
+            .extend(plan_prune(config, zone_config, zone_state));
+    }
+    plan
+}
+
+/// 计算所有配置的 zone 和已经去掉的 zone 需要执行的操作
+pub fn plan(client: &CfClient, config: &Config, ips: &Ips, managed: &ManagedZones) -> Plan {
+    let mut owner = Owner::new(config);
+    let (state, errors) = fetch_state(client, config, &mut owner, managed);
+    let mut plan = plan_from_state(config, ips, &state);
+    plan.errors = errors;
+    plan
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::RecordContent;
+
+    fn config() -> Config {
+        Config {
+            device: "home".to_string(),
+            zones: vec![ZoneConfig {
+                name: "example.com".to_string(),
+                records: vec![DnsRecordConfig {
+                    name: "www".to_string(),
+                    dns_type: DnsType::All,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn actual(id: &str, type_field: &str, content: &str) -> DnsRecordResult {
+        DnsRecordResult {
+            id: id.to_string(),
+            name: "www.example.com".to_string(),
+            type_field: type_field.to_string(),
+            content: content.to_string(),
+            comment: Some("[home] ".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn plan_for(ips: &[&str], actual: &[DnsRecordResult]) -> Vec<Operation> {
+        let config = config();
+        let ips = Ips {
+            public: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            ..Default::default()
+        };
+        let desired = desired_records(&config, &config.zones[0], &ips);
+        plan_zone("example.com", "zone", &desired, actual)
+    }
+
+    #[test]
+    fn test_plan_creates_missing_records() {
+        let operations = plan_for(&["1.1.1.1", "2001:db8::1"], &[]);
+        assert_eq!(operations.len(), 2);
+        assert!(operations
+            .iter()
+            .all(|op| matches!(op, Operation::Create { .. })));
+    }
+
+    #[test]
+    fn test_plan_is_empty_when_in_sync() {
+        let operations = plan_for(&["1.1.1.1"], &[actual("a", "A", "1.1.1.1")]);
+        assert!(operations.is_empty());
+    }
+
+    #[test]
+    fn test_plan_updates_changed_address_in_place() {
+        let operations = plan_for(&["2.2.2.2"], &[actual("a", "A", "1.1.1.1")]);
+        assert_eq!(operations.len(), 1);
+        match &operations[0] {
+            Operation::Update { current, patch, .. } => {
+                assert_eq!(current.id, "a");
+                assert_eq!(patch.content.as_deref(), Some("2.2.2.2"));
+                assert!(patch.comment.is_none());
+            }
+            op => panic!("unexpected operation: {:?}", op),
+        }
+    }
+
+    #[test]
+    fn test_plan_deletes_records_no_longer_desired() {
+        let operations = plan_for(
+            &["1.1.1.1"],
+            &[
+                actual("a", "A", "1.1.1.1"),
+                actual("b", "AAAA", "2001:db8::1"),
+            ],
+        );
+        assert_eq!(operations.len(), 1);
+        assert!(matches!(&operations[0], Operation::Delete { record, .. } if record.id == "b"));
+    }
+
+    #[test]
+    fn test_plan_prune() {
+        let mut config = config();
+        let ips = Ips {
+            public: vec!["1.1.1.1".parse().unwrap()],
+            ..Default::default()
+        };
+        let synced = actual("a", "A", "1.1.1.1");
+        let mut removed = actual("b", "A", "1.1.1.1");
+        removed.name = "old.example.com".to_string();
+        let mut heritage = actual("c", "TXT", "heritage=cf-ddns,device=home,type=A");
+        heritage.name = "_cf-ddns.www.example.com".to_string();
+        // 配置中的记录名下的其他记录不会被当作已去掉的记录
+        let mut other = actual("d", "MX", "mail.example.com");
+        other.name = "www.example.com".to_string();
+        let state = [ZoneState {
+            zone: "example.com".to_string(),
+            zone_id: "zone".to_string(),
+            actual: vec![synced.clone()],
+            owned: vec![synced, removed, heritage, other],
+        }];
+
+        let plan = plan_from_state(&config, &ips, &state);
+        assert_eq!(plan.operations.len(), 1);
+        assert!(
+            matches!(&plan.operations[0], Operation::Delete { record, .. } if record.id == "b")
+        );
+
+        for prune in [PrunePolicy::Keep, PrunePolicy::Warn] {
+            config.prune = prune;
+            assert!(plan_from_state(&config, &ips, &state).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_dropped_zone() {
+        let mut config = config();
+        let ips = Ips {
+            public: vec!["1.1.1.1".parse().unwrap()],
+            ..Default::default()
+        };
+        let mut left = actual("a", "A", "1.1.1.1");
+        left.name = "www.example.net".to_string();
+        let mut state = vec![ZoneState {
+            zone: "example.net".to_string(),
+            zone_id: "zone2".to_string(),
+            actual: Vec::new(),
+            owned: vec![left],
+        }];
+        let mut managed = ManagedZones(BTreeMap::from([
+            ("example.com".to_string(), "zone".to_string()),
+            ("example.net".to_string(), "zone2".to_string()),
+        ]));
+        assert_eq!(
+            managed.dropped(&config).collect::<Vec<_>>(),
+            vec![("example.net", "zone2")]
+        );
+
+        // 已经去掉的 zone 中的记录全部删除
+        let plan = plan_from_state(&config, &ips, &state);
+        assert_eq!(plan.operations.len(), 1);
+        assert!(
+            matches!(&plan.operations[0], Operation::Delete { zone, record, .. } if zone == "example.net" && record.id == "a")
+        );
+        // 删除失败时下次继续处理
+        managed.update(&config, &state, false);
+        assert!(managed.0.contains_key("example.net"));
+        managed.update(&config, &state, true);
+        assert!(!managed.0.contains_key("example.net"));
+
+        // warn 时一直保留，keep 时直接忘掉
+        config.prune = PrunePolicy::Warn;
+        managed
+            .0
+            .insert("example.net".to_string(), "zone2".to_string());
+        assert!(plan_from_state(&config, &ips, &state).is_empty());
+        managed.update(&config, &state, true);
+        assert!(managed.0.contains_key("example.net"));
+        config.prune = PrunePolicy::Keep;
+        state.clear();
+        managed.update(&config, &state, true);
+        assert_eq!(managed.0.keys().collect::<Vec<_>>(), vec!["example.com"]);
+    }
+
+    #[test]
+    fn test_plan_collapses_duplicates() {
+        let operations = plan_for(
+            &["1.1.1.1", "2.2.2.2"],
+            &[
+                actual("a", "A", "1.1.1.1"),
+                actual("b", "A", "1.1.1.1"),
+                actual("c", "A", "1.1.1.1"),
+                actual("d", "A", "3.3.3.3"),
+                actual("e", "A", "3.3.3.3"),
+            ],
+        );
+        let plan = Plan {
+            operations,
+            ..Default::default()
+        };
+        // b 原地改为 2.2.2.2，c 和 a 重复，d、e 不再需要
+        assert_eq!(plan.summary(), (0, 1, 3));
+        assert_eq!(plan.duplicates(), 1);
+        let duplicates: Vec<_> = plan
+            .operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Delete {
+                    record,
+                    duplicate_of,
+                    ..
+                } => Some((record.id.as_str(), duplicate_of.as_deref())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(duplicates, vec![("c", Some("a")), ("d", None), ("e", None)]);
+    }
+
+    #[test]
+    fn test_plan_record_source_and_content() {
+        let mut config = config();
+        config.zones[0].records.push(DnsRecordConfig {
+            name: "lan".to_string(),
+            content: RecordContent::Addresses(vec!["192.168.1.2".parse().unwrap()]),
+            ..Default::default()
+        });
+        config.zones[0].records[0].source = vec![crate::config::IpProviderConfig::Interface {
+            interfaces: vec!["eth1".to_string()],
+            multiple: false,
+            include_private: false,
+            include_temporary: false,
+        }];
+        let ips = Ips {
+            public: vec!["1.1.1.1".parse().unwrap()],
+            ..Default::default()
+        };
+        // 来源没有获取到地址，已有的记录保持不变
+        let state = [ZoneState {
+            zone: "example.com".to_string(),
+            zone_id: "zone".to_string(),
+            actual: vec![actual("a", "A", "2.2.2.2")],
+            owned: Vec::new(),
+        }];
+        let plan = plan_from_state(&config, &ips, &state);
+        assert_eq!(plan.operations.len(), 1);
+        assert!(matches!(
+            &plan.operations[0],
+            Operation::Create { record, .. } if record.name == "lan.example.com" && record.content == "192.168.1.2"
+        ));
+    }
+
+    #[test]
+    fn test_combined_records() {
+        let v4: Vec<IpAddr> = vec!["203.0.113.7".parse().unwrap()];
+        let v6: Vec<IpAddr> = vec!["2001:db8::1".parse().unwrap()];
+        let record = |dns_type: DnsType| DnsRecordConfig {
+            name: "www".to_string(),
+            dns_type,
+            params: Some("alpn=\"h3,h2\"".to_string()),
+            ..Default::default()
+        };
+        let spf = combined_record("home", &record(DnsType::Spf), &v4, &v6).unwrap();
+        assert_eq!(spf.type_field, "TXT");
+        assert_eq!(spf.content, "v=spf1 ip4:203.0.113.7 ip6:2001:db8::1 ~all");
+        let txt = combined_record("home", &record(DnsType::Txt), &v4, &[]).unwrap();
+        assert_eq!(txt.content, "203.0.113.7");
+        let https = combined_record("home", &record(DnsType::Https), &v4, &v6).unwrap();
+        assert_eq!(
+            https.display_content(),
+            "1 . alpn=\"h3,h2\" ipv4hint=\"203.0.113.7\" ipv6hint=\"2001:db8::1\""
+        );
+        assert!(combined_record("home", &record(DnsType::Txt), &[], &[]).is_none());
+
+        // 引号不同的 TXT 记录视为相同
+        let actual = DnsRecordResult {
+            content: format!("\"{}\"", spf.content),
+            ..Default::default()
+        };
+        assert!(same_content(&spf, &actual));
+    }
+
+    #[test]
+    fn test_template_record() {
+        let record = DnsRecordConfig {
+            name: "status".to_string(),
+            dns_type: DnsType::Txt,
+            content: RecordContent::Template("{device} {ipv4} {timestamp}".to_string()),
+            ..Default::default()
+        };
+        let v4: Vec<IpAddr> = vec!["203.0.113.7".parse().unwrap()];
+        let txt = combined_record("home", &record, &v4, &[]).unwrap();
+        assert!(txt.content.starts_with("home 203.0.113.7 "));
+
+        // 只有时间戳不同时不需要更新
+        let actual = |content: &str| DnsRecordResult {
+            content: content.to_string(),
+            ..Default::default()
+        };
+        assert!(same_content(&txt, &actual("home 203.0.113.7 1700000000")));
+        assert!(!same_content(&txt, &actual("home 198.51.100.1 1700000000")));
+        assert_eq!(txt.proxied, Some(false));
+
+        // CNAME 使用配置中的 proxied
+        let record = DnsRecordConfig {
+            name: "alias".to_string(),
+            dns_type: DnsType::Cname,
+            content: RecordContent::Template("{device}.example.net".to_string()),
+            proxied: true,
+            ..Default::default()
+        };
+        let cname = combined_record("home", &record, &v4, &[]).unwrap();
+        assert_eq!(cname.content, "home.example.net");
+        assert_eq!(cname.proxied, Some(true));
+        let current = DnsRecordResult {
+            content: "home.example.net".to_string(),
+            proxied: true,
+            ..Default::default()
+        };
+        assert!(record_patch(&cname, &current).proxied.is_none());
+    }
+
+    #[test]
+    fn test_uri_record() {
+        let record = DnsRecordConfig {
+            name: "_ddns._tcp".to_string(),
+            dns_type: DnsType::Uri,
+            content: RecordContent::Template("http://[{ipv6}]:8080/?t={timestamp}".to_string()),
+            priority: Some(10),
+            ..Default::default()
+        };
+        let v6: Vec<IpAddr> = vec!["2001:db8::1".parse().unwrap()];
+        let uri = combined_record("home", &record, &[], &v6).unwrap();
+        assert_eq!(uri.type_field, "URI");
+        assert!(uri.content.is_empty());
+        assert_eq!(uri.priority, Some(10));
+        assert!(uri
+            .display_content()
+            .starts_with("10 1 \"http://[2001:db8::1]:8080/?t="));
+
+        let actual = |priority: u16, target: &str| DnsRecordResult {
+            type_field: "URI".to_string(),
+            content: format!("1 \"{}\"", target),
+            data: Some(serde_json::json!({ "weight": 1, "target": target })),
+            priority: Some(priority),
+            ..Default::default()
+        };
+        // 只有时间戳不同时不需要更新
+        assert!(same_content(
+            &uri,
+            &actual(10, "http://[2001:db8::1]:8080/?t=1700000000")
+        ));
+        let patch = record_patch(&uri, &actual(20, "http://[2001:db8::1]:8080/?t=1"));
+        assert_eq!(patch.priority, Some(10));
+        assert_eq!(patch.data, uri.data);
+        assert!(!same_content(
+            &uri,
+            &actual(10, "http://[2001:db8::2]:8080/?t=1700000000")
+        ));
+    }
+
+    #[test]
+    fn test_plan_order_keeps_marker() {
+        let owner = Owner {
+            device: "home".to_string(),
+            strategy: crate::config::OwnershipStrategy::Txt,
+            ..Default::default()
+        };
+        let mut heritage = actual("h", "TXT", "heritage=cf-ddns,device=home,type=A");
+        heritage.name = "_cf-ddns.www.example.com".to_string();
+        let delete = |record: DnsRecordResult| Operation::Delete {
+            zone: "example.com".to_string(),
+            zone_id: "zone".to_string(),
+            record,
+            duplicate_of: None,
+        };
+        let create = |name: &str| Operation::Create {
+            zone: "example.com".to_string(),
+            zone_id: "zone".to_string(),
+            record: CfDnsRecord {
+                name: name.to_string(),
+                ..Default::default()
+            },
+        };
+        // plan_zone 按记录名排序，heritage 记录排在它标记的记录前面
+        let plan = Plan {
+            operations: vec![
+                delete(heritage),
+                delete(actual("a", "A", "1.1.1.1")),
+                create("www.example.com"),
+                create("_cf-ddns.www.example.com"),
+            ],
+            ..Default::default()
+        };
+        let order: Vec<_> = plan
+            .ordered(&owner)
+            .into_iter()
+            .map(|op| match op {
+                Operation::Create { record, .. } => record.name.clone(),
+                Operation::Update { current, .. } => current.id.clone(),
+                Operation::Delete { record, .. } => record.id.clone(),
+            })
+            .collect();
+        assert_eq!(
+            order,
+            vec!["_cf-ddns.www.example.com", "www.example.com", "a", "h"]
+        );
+    }
+
+    #[test]
+    fn test_template_with_unresolved_family() {
+        let mut config = config();
+        config.zones[0].records = vec![
+            DnsRecordConfig {
+                name: "alias".to_string(),
+                dns_type: DnsType::Cname,
+                content: RecordContent::Template("{device}.example.net".to_string()),
+                ..Default::default()
+            },
+            DnsRecordConfig {
+                name: "status".to_string(),
+                dns_type: DnsType::Txt,
+                content: RecordContent::Template("{ipv4} {ipv6}".to_string()),
+                ..Default::default()
+            },
+        ];
+        let ips = Ips {
+            public: vec!["203.0.113.7".parse().unwrap()],
+            unresolved: vec![IpFamily::V6],
+            ..Default::default()
+        };
+        // 没有用到地址的模板不受无法确定的协议族影响
+        let desired = desired_records(&config, &config.zones[0], &ips);
+        assert_eq!(desired.len(), 1);
+        assert_eq!(desired[0].name, "alias.example.com");
+        assert_eq!(desired[0].content, "home.example.net");
+        assert_eq!(
+            unresolved_records(&config.zones[0], &ips),
+            vec![("status.example.com".to_string(), "TXT")]
+        );
+    }
+
+    #[test]
+    fn test_empty_comment() {
+        let desired = CfDnsRecord {
+            content: "1.1.1.1".to_string(),
+            comment: Some(String::new()),
+            ..Default::default()
+        };
+        let mut current = DnsRecordResult {
+            content: "1.1.1.1".to_string(),
+            ..Default::default()
+        };
+        assert!(record_patch(&desired, &current).is_empty());
+        current.comment = Some("[home] ".to_string());
+        assert_eq!(
+            record_patch(&desired, &current).comment.as_deref(),
+            Some("")
+        );
+    }
+
+    #[test]
+    fn test_plan_display() {
+        let mut plan = Plan {
+            operations: plan_for(&["2.2.2.2"], &[actual("a", "A", "1.1.1.1")]),
+            ..Default::default()
+        };
+        assert_eq!(
+            plan.to_string(),
+            "~ update A www.example.com 1.1.1.1 (zone: example.com, id: a)\n    content: 1.1.1.1 -> 2.2.2.2\nPlan: 0 to create, 1 to update, 0 to delete."
+        );
+
+        // 读取状态失败时不能显示为已是最新
+        plan.operations.clear();
+        plan.errors
+            .push("zone example.com: failed to get zone id".to_string());
+        assert_eq!(
+            plan.to_string(),
+            "! failed to read zone example.com: failed to get zone id\nNo changes planned, but the state is incomplete."
+        );
+    }
+}
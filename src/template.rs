@@ -0,0 +1,112 @@
+use std::{
+    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// 模板中可以使用的变量
+pub const VARIABLES: [&str; 5] = ["ipv4", "ipv6", "device", "timestamp", "hostname"];
+
+const TIMESTAMP: &str = "{timestamp}";
+
+/// 模板中使用的变量名，未闭合的 `{` 按普通字符处理
+pub fn variables(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        names.push(&rest[..end]);
+        rest = &rest[end + 1..];
+    }
+    names
+}
+
+/// 渲染模板中除 `{timestamp}` 以外的变量，同一协议族的多个地址以空格分隔
+pub fn render_without_timestamp(template: &str, device: &str, ips: &[IpAddr]) -> String {
+    let join = |v4: bool| {
+        ips.iter()
+            .filter(|ip| ip.is_ipv4() == v4)
+            .map(|ip| ip.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    template
+        .replace("{ipv4}", &join(true))
+        .replace("{ipv6}", &join(false))
+        .replace("{device}", device)
+        .replace("{hostname}", &hostname())
+}
+
+/// 填入当前时间戳
+pub fn fill_timestamp(rendered: &str) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    rendered.replace(TIMESTAMP, &now.to_string())
+}
+
+/// 内容是否由渲染后的模板生成，`{timestamp}` 匹配任意时间戳，
+/// 这样只有地址等变量变化时才需要更新记录
+pub fn matches(rendered: &str, content: &str) -> bool {
+    let mut parts = rendered.split(TIMESTAMP);
+    let Some(mut rest) = parts.next().and_then(|first| content.strip_prefix(first)) else {
+        return false;
+    };
+    for part in parts {
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return false;
+        }
+        let Some(next) = rest[digits..].strip_prefix(part) else {
+            return false;
+        };
+        rest = next;
+    }
+    rest.is_empty()
+}
+
+/// 本机的主机名，获取不到时为空
+pub fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_template() {
+    let ips: Vec<IpAddr> = vec![
+        "203.0.113.7".parse().unwrap(),
+        "2001:db8::1".parse().unwrap(),
+    ];
+    let template = "ip={ipv4} ip6={ipv6} dev={device} at={timestamp}";
+    assert_eq!(
+        variables(template),
+        vec!["ipv4", "ipv6", "device", "timestamp"]
+    );
+
+    let rendered = render_without_timestamp(template, "home", &ips);
+    assert_eq!(
+        rendered,
+        "ip=203.0.113.7 ip6=2001:db8::1 dev=home at={timestamp}"
+    );
+    assert!(matches(&rendered, &fill_timestamp(&rendered)));
+    assert!(matches(
+        &rendered,
+        "ip=203.0.113.7 ip6=2001:db8::1 dev=home at=1700000000"
+    ));
+    assert!(!matches(
+        &rendered,
+        "ip=198.51.100.1 ip6=2001:db8::1 dev=home at=1700000000"
+    ));
+    assert!(!matches(
+        &rendered,
+        "ip=203.0.113.7 ip6=2001:db8::1 dev=home at="
+    ));
+}
@@ -0,0 +1,178 @@
+use std::{thread::sleep, time::Duration};
+
+/// 网卡地址变化后等待一段时间再同步，合并短时间内的多次变化，也给新地址留出完成 DAD 的时间
+#[cfg(target_os = "linux")]
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// 监听网卡地址的增加和删除，只在 Linux 上可用
+pub struct AddrWatcher {
+    #[cfg(target_os = "linux")]
+    fd: std::os::fd::OwnedFd,
+}
+
+impl AddrWatcher {
+    /// 订阅 netlink 的 IPv4/IPv6 地址变化
+    #[cfg(target_os = "linux")]
+    pub fn new() -> std::io::Result<Self> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        // SAFETY: 参数都是常量，返回值在下面检查
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: fd 是刚创建的有效描述符，由 OwnedFd 负责关闭
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: sockaddr_nl 全零是合法的初始值
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = (libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+        // SAFETY: addr 在调用期间有效，长度与类型一致
+        let ret = unsafe {
+            libc::bind(
+                std::os::fd::AsRawFd::as_raw_fd(&fd),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self { fd })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new() -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "watching address changes is only supported on Linux",
+        ))
+    }
+
+    /// 等待地址变化，最多等待 timeout，返回是否有地址变化
+    #[cfg(target_os = "linux")]
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            if remaining.is_zero() || !self.poll(remaining) {
+                return false;
+            }
+            // 收到的都是其他消息时继续等待剩余时间
+            if self.drain() {
+                break;
+            }
+        }
+        // 合并随后一段时间内的变化
+        sleep(SETTLE);
+        self.drain();
+        true
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn wait(&self, timeout: Duration) -> bool {
+        sleep(timeout);
+        false
+    }
+
+    /// 描述符是否在 timeout 内可读
+    #[cfg(target_os = "linux")]
+    fn poll(&self, timeout: Duration) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd: std::os::fd::AsRawFd::as_raw_fd(&self.fd),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+        // SAFETY: pollfd 在调用期间有效，数量为 1
+        let ret = unsafe { libc::poll(&mut pollfd, 1, timeout) };
+        if ret < 0 {
+            // 被信号打断等情况，退回到轮询
+            eprintln!(
+                "Failed to wait for address changes: {}",
+                std::io::Error::last_os_error()
+            );
+            return false;
+        }
+        ret > 0
+    }
+
+    /// 读出所有待处理的消息，返回其中是否有地址的增加或删除
+    #[cfg(target_os = "linux")]
+    fn drain(&self) -> bool {
+        let mut changed = false;
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            // SAFETY: buf 在调用期间有效，长度与 buf 一致
+            let len = unsafe {
+                libc::recv(
+                    std::os::fd::AsRawFd::as_raw_fd(&self.fd),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if len <= 0 {
+                // EAGAIN 表示已经读完，ENOBUFS 表示消息太多被丢弃，同样需要同步
+                let err = std::io::Error::last_os_error();
+                changed |= len < 0 && err.raw_os_error() == Some(libc::ENOBUFS);
+                return changed;
+            }
+            changed |= has_addr_change(&buf[..len as usize]);
+        }
+    }
+}
+
+/// 一次读到的 netlink 消息中是否有 RTM_NEWADDR 或 RTM_DELADDR
+#[cfg(target_os = "linux")]
+fn has_addr_change(mut buf: &[u8]) -> bool {
+    const HEADER_LEN: usize = std::mem::size_of::<libc::nlmsghdr>();
+    while buf.len() >= HEADER_LEN {
+        // nlmsghdr 的前 4 个字节为消息长度，随后 2 个字节为消息类型，均为本机字节序
+        let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+        if kind == libc::RTM_NEWADDR || kind == libc::RTM_DELADDR {
+            return true;
+        }
+        if len < HEADER_LEN {
+            break;
+        }
+        // 消息按 4 字节对齐
+        let next = (len + 3) & !3;
+        buf = buf.get(next..).unwrap_or_default();
+    }
+    false
+}
+
+/// 等到下一次同步的时间，有 watcher 时地址变化会提前返回
+pub fn wait_for_change(watcher: Option<&AddrWatcher>, interval: Duration) -> bool {
+    match watcher {
+        Some(watcher) => watcher.wait(interval),
+        None => {
+            sleep(interval);
+            false
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_has_addr_change() {
+    let message = |kind: u16| {
+        let mut msg = vec![0u8; 20];
+        msg[..4].copy_from_slice(&20u32.to_ne_bytes());
+        msg[4..6].copy_from_slice(&kind.to_ne_bytes());
+        msg
+    };
+    assert!(!has_addr_change(&message(libc::RTM_NEWLINK)));
+    assert!(has_addr_change(&message(libc::RTM_DELADDR)));
+    let both = [message(libc::RTM_NEWLINK), message(libc::RTM_NEWADDR)].concat();
+    assert!(has_addr_change(&both));
+}